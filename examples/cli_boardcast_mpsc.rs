@@ -4,6 +4,7 @@ use vielpork::downloader::Downloader;
 use vielpork::error::Result;
 use vielpork::reporters::cli_boardcast_mpsc::CliReporterBoardcastMpsc;
 use vielpork::resolvers::url::UrlResolver;
+use vielpork::stores::json::JsonStateStore;
 
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -19,6 +20,7 @@ async fn main() -> Result<()> {
         options,
         Box::new(UrlResolver::new()),
         Box::new((*reporter).clone()),
+        Box::new(JsonStateStore::in_dir("fetch")),
     )));
 
     let mut rx = reporter.subscribe_mpsc();
@@ -26,16 +28,21 @@ async fn main() -> Result<()> {
     tokio::spawn(async move {
         while let Some(event) = rx.recv().await {
             match event {
-                ProgressEvent::Start { task_id, total } => {
+                ProgressEvent::Start {
+                    task_id,
+                    total,
+                    offset,
+                } => {
                     println!(
-                        "Starting download of beatmapset {} with total size {}",
-                        task_id, total
+                        "Starting download of beatmapset {} with total size {:?} from byte {}",
+                        task_id, total, offset
                     );
                 }
                 ProgressEvent::Update { task_id, progress } => {
                     println!(
-                        "Downloading beatmapset {} - {}%",
-                        task_id, progress.progress_percentage
+                        "Downloading beatmapset {} - {:.2}%",
+                        task_id,
+                        progress.progress_percentage.unwrap_or_default()
                     );
                 }
 
@@ -6,6 +6,7 @@ use vielpork::downloader::Downloader;
 use vielpork::error::Result;
 use vielpork::reporters::tui::TuiReporter;
 use vielpork::resolvers::url::UrlResolver;
+use vielpork::stores::json::JsonStateStore;
 
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -20,6 +21,7 @@ async fn main() -> Result<()> {
         options,
         Box::new(UrlResolver::new()),
         Box::new(TuiReporter::new()),
+        Box::new(JsonStateStore::in_dir("fetch")),
     )));
 
     let resources = vec![
@@ -50,37 +52,37 @@ async fn main() -> Result<()> {
     println!("Resuming in 2 seconds");
     tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
     println!("Resuming");
-    println!("");
-    println!("");
+    println!();
+    println!();
     downloader.lock().await.resume().await?;
 
     tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
     downloader.lock().await.pause().await?;
     println!("Paused");
     println!("Resuming in 2 seconds");
-    println!("");
-    println!("");
+    println!();
+    println!();
     tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
     println!("Resuming");
-    println!("");
-    println!("");
+    println!();
+    println!();
     downloader.lock().await.resume().await?;
 
     tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
 
-    println!("");
-    println!("");
+    println!();
+    println!();
 
-    println!("");
-    println!("");
-    println!("");
-    println!("");
-    println!("");
-    println!("");
-    println!("");
+    println!();
+    println!();
+    println!();
+    println!();
+    println!();
+    println!();
+    println!();
 
-    println!("");
-    println!("");
+    println!();
+    println!();
     downloader.lock().await.stop().await?;
 
     tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
@@ -1,6 +1,7 @@
 use crate::base::enums::DownloadResource;
-use crate::base::structs::{DownloadMeta, ResolvedResource};
+use crate::base::structs::{DownloadMeta, ResolvedResource, Segment};
 use crate::error::Result;
+use crate::hash::{HashAlgorithm, StreamingHasher, to_hex};
 use crate::template::{TemplateContext, TemplateRenderer};
 // use crate::hash::{HashSource, HashFormat};
 
@@ -12,45 +13,51 @@ use std::time::Duration;
 // 函数一：计算当前下载速度
 pub fn rate(downloaded: u64, elapsed: Duration) -> f64 {
     let elapsed = elapsed.as_secs_f64();
+    // 确保 elapsed 大于 0
+    if elapsed <= 0.0 {
+        return 0.0;
+    }
     let downloaded = downloaded as f64;
-    let rate = downloaded / elapsed;
-    rate
+    downloaded / elapsed
 }
 
-// 函数二：计算剩余时间
-pub fn remaining_time(downloaded: u64, total: u64, elapsed: Duration) -> Duration {
+// 函数二：计算剩余时间（总大小未知时为None）
+pub fn remaining_time(downloaded: u64, total: Option<u64>, rate: f64) -> Option<Duration> {
+    let total = total?;
     // 确保 downloaded 不大于 total
     if downloaded >= total {
-        return Duration::from_secs(0);
+        return Some(Duration::from_secs(0));
     }
 
-    let rate = rate(downloaded, elapsed);
     // 确保速率大于 0
     if rate <= 0.0 {
-        return Duration::from_secs(0);
+        return None;
     }
 
     let remaining = (total as f64) - (downloaded as f64);
     let remaining_time = remaining / rate;
-    Duration::from_secs_f64(remaining_time)
+    Some(Duration::from_secs_f64(remaining_time))
 }
 
-// 函数三：计算下载进度
-pub fn progress(downloaded: u64, total: u64) -> f64 {
+// 函数三：计算下载进度（总大小未知时为None）
+pub fn progress(downloaded: u64, total: Option<u64>) -> Option<f64> {
+    let total = total?;
+    if total == 0 {
+        return Some(1.0);
+    }
     let downloaded = downloaded as f64;
     let total = total as f64;
-    let progress = downloaded / total;
-    progress
+    Some(downloaded / total)
 }
 
 // 函数四：计算下载速度、剩余时间、下载进度
 pub fn rate_remaining_progress(
     downloaded: u64,
-    total: u64,
+    total: Option<u64>,
     elapsed: Duration,
-) -> (f64, Duration, f64) {
+) -> (f64, Option<Duration>, Option<f64>) {
     let rate = rate(downloaded, elapsed);
-    let remaining_time = remaining_time(downloaded, total, elapsed);
+    let remaining_time = remaining_time(downloaded, total, rate);
     let progress = progress(downloaded, total);
     (rate, remaining_time, progress)
 }
@@ -117,14 +124,19 @@ pub async fn organize_by_type(meta: &DownloadMeta) -> Result<PathBuf> {
 }
 
 pub async fn organize_by_domain(resolved: &ResolvedResource) -> Result<PathBuf> {
-    let domain = reqwest::Url::parse(&resolved.url)
-        .map_err(|_| "Invalid URL")?
-        .host_str()
-        .map(|h| h.to_string())
-        .unwrap_or_else(|| "unknown".into());
+    reqwest::Url::parse(&resolved.url).map_err(|_| "Invalid URL")?;
+    let domain = url_host(&resolved.url).unwrap_or_else(|| "unknown".into());
     Ok(PathBuf::from(domain))
 }
 
+/// URL中的主机名，按域名组织目录、按主机限制连接与调度时使用
+pub fn url_host(url: &str) -> Option<String> {
+    reqwest::Url::parse(url)
+        .ok()?
+        .host_str()
+        .map(str::to_string)
+}
+
 pub async fn auto_filename(resolved: &ResolvedResource, meta: &DownloadMeta) -> Result<String> {
     // 优先从 Content-Disposition 头获取
     if let Some(disposition) = resolved
@@ -141,7 +153,7 @@ pub async fn auto_filename(resolved: &ResolvedResource, meta: &DownloadMeta) ->
     // 从 URL 路径获取文件名
     if let Some(url_filename) = reqwest::Url::parse(&resolved.url).ok().and_then(|u| {
         u.path_segments()
-            .and_then(|s| s.last().map(|s| s.to_string()))
+            .and_then(|mut s| s.next_back().map(|s| s.to_string()))
     }) {
         if url_filename.is_empty() {
             return generate_random_filename(meta);
@@ -217,7 +229,7 @@ pub async fn custom_filename(
     let context = TemplateContext {
         url: &resolved.url,
         domain: domain.as_deref(),
-        filename: meta.suggested_filename.as_deref().unwrap_or_else(|| "file"),
+        filename: meta.suggested_filename.as_deref().unwrap_or("file"),
         extension: meta
             .suggested_filename
             .as_ref()
@@ -238,9 +250,7 @@ pub async fn custom_filename(
     // println!("Template: {}", template);
     // println!("Context: {:?}", context);
     // 渲染模板
-    let raw_name = renderer
-        .render_path_template(template, &context)
-        .map_err(|e| e)?;
+    let raw_name = renderer.render_path_template(template, &context)?;
 
     // println!("Raw name: {}", raw_name);
 
@@ -261,9 +271,7 @@ pub async fn custom_directory(
     context: &TemplateContext<'_>,
     renderer: &TemplateRenderer,
 ) -> Result<PathBuf> {
-    let dir_path = renderer
-        .render_path_template(template, context)
-        .map_err(|e| e)?;
+    let dir_path = renderer.render_path_template(template, context)?;
 
     // 清理路径中的非法字符
     let sanitized_path = sanitize_path(&dir_path)?;
@@ -304,11 +312,178 @@ fn sanitize_path(path: &str) -> Result<String> {
         .ok_or("Invalid path".into())
 }
 
-// 根据输入的字符串，生成8~14位数u32，不用哈希
+/// 计算第 attempt 次重试前的等待时间（指数退避 + 随机抖动）
+///
+/// 退避上限为 base * 2^(attempt-1)（不超过 max），实际等待时间在上限的一半到上限之间随机
+pub fn backoff_delay(attempt: u32, base: Duration, max: Duration) -> Duration {
+    let factor = 1u32
+        .checked_shl(attempt.saturating_sub(1))
+        .unwrap_or(u32::MAX);
+    let ceiling = base.saturating_mul(factor).min(max);
+    let half = ceiling / 2;
+    half + half.mul_f64(rand::random::<f64>())
+}
+
+/// 解析Retry-After头（秒数或HTTP日期），返回需要等待的秒数
+pub fn parse_retry_after(value: &str) -> Option<u64> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(seconds);
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&Utc) - Utc::now()).num_seconds().max(0) as u64)
+}
+
+/// 分段下载时，自动模式下每个分段的最小大小
+const MIN_SEGMENT_SIZE: u64 = 1024 * 1024;
+
+/// 将 [start, total) 切分为若干字节范围分段
+///
+/// 指定 chunk_size 时按固定大小切分；否则按连接数平均切分，且每段不小于 1 MiB
+pub fn split_segments(
+    start: u64,
+    total: u64,
+    chunk_size: Option<u64>,
+    connections: u32,
+) -> Vec<Segment> {
+    if start >= total {
+        return Vec::new();
+    }
+    let remaining = total - start;
+    let size = chunk_size.filter(|s| *s > 0).unwrap_or_else(|| {
+        remaining
+            .div_ceil(connections.max(1) as u64)
+            .max(MIN_SEGMENT_SIZE)
+    });
+
+    let mut segments = Vec::new();
+    let mut offset = start;
+    while offset < total {
+        let end = (offset + size).min(total) - 1;
+        segments.push(Segment { start: offset, end });
+        offset = end + 1;
+    }
+    segments
+}
+
+/// 计算从 start 开始、已连续写入磁盘的字节数上界
+///
+/// written 与 segments 一一对应，表示每个分段已写入的字节数
+pub fn contiguous_prefix(start: u64, segments: &[Segment], written: &[u64]) -> u64 {
+    let mut prefix = start;
+    for (segment, written) in segments.iter().zip(written) {
+        if *written < segment.len() {
+            return segment.start + written;
+        }
+        prefix = segment.end + 1;
+    }
+    prefix
+}
+
+/// 根据输入的字符串生成资源ID（SHA-256的前4字节），解析器用它填充 ResolvedResource::id
+///
+/// 任务本身使用 Downloader 分配的 TaskId，去重使用 content_key
 pub fn generate_task_id(input: &str) -> u32 {
-    let mut id = 0;
-    for (i, c) in input.chars().enumerate() {
-        id += (c as u32) * ((i + 1) as u32);
+    let mut hasher = StreamingHasher::new(HashAlgorithm::Sha256);
+    hasher.update(input.as_bytes());
+    let digest = hasher.finalize();
+    u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]])
+}
+
+/// 规范化URL：协议与主机小写、去掉默认端口与片段；无法解析时只去掉首尾空白
+pub fn normalize_url(url: &str) -> String {
+    match reqwest::Url::parse(url.trim()) {
+        Ok(mut url) => {
+            url.set_fragment(None);
+            url.to_string()
+        }
+        Err(_) => url.trim().to_string(),
+    }
+}
+
+/// 资源的内容键：规范化URL的SHA-256（十六进制），相同的资源在不同会话中得到相同的键
+pub fn content_key(url: &str) -> String {
+    let mut hasher = StreamingHasher::new(HashAlgorithm::Sha256);
+    hasher.update(normalize_url(url).as_bytes());
+    to_hex(&hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_key() {
+        assert_eq!(
+            content_key("HTTP://Example.com:80/a.bin#top"),
+            content_key("http://example.com/a.bin")
+        );
+        assert_ne!(
+            content_key("http://example.com/ab"),
+            content_key("http://example.com/ba")
+        );
+        assert_eq!(content_key("http://example.com/").len(), 64);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(120));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(0));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn test_split_segments() {
+        let segments = split_segments(0, 10, Some(4), 4);
+        assert_eq!(
+            segments,
+            vec![
+                Segment { start: 0, end: 3 },
+                Segment { start: 4, end: 7 },
+                Segment { start: 8, end: 9 },
+            ]
+        );
+        assert_eq!(segments.iter().map(|s| s.len()).sum::<u64>(), 10);
+
+        // 自动模式下小文件不会被切分
+        assert_eq!(split_segments(100, 1000, None, 4).len(), 1);
+        assert_eq!(split_segments(0, 8 * MIN_SEGMENT_SIZE, None, 4).len(), 4);
+        assert!(split_segments(10, 10, None, 4).is_empty());
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        let base = Duration::from_millis(100);
+        let max = Duration::from_secs(1);
+        for attempt in 1..=10 {
+            let ceiling = (base * 2u32.pow(attempt - 1)).min(max);
+            let delay = backoff_delay(attempt, base, max);
+            assert!(delay >= ceiling / 2 && delay <= ceiling);
+        }
+        assert!(backoff_delay(100, base, max) <= max);
+    }
+
+    #[test]
+    fn test_contiguous_prefix() {
+        let segments = split_segments(0, 10, Some(4), 4);
+        assert_eq!(contiguous_prefix(0, &segments, &[4, 4, 2]), 10);
+        assert_eq!(contiguous_prefix(0, &segments, &[4, 1, 2]), 5);
+        assert_eq!(contiguous_prefix(0, &segments, &[0, 4, 2]), 0);
+    }
+
+    #[test]
+    fn test_unknown_total() {
+        let elapsed = Duration::from_secs(2);
+        let (rate, remaining, progress) = rate_remaining_progress(100, None, elapsed);
+        assert_eq!(rate, 50.0);
+        assert!(remaining.is_none() && progress.is_none());
+
+        let (_, remaining, progress) = rate_remaining_progress(100, Some(200), elapsed);
+        assert_eq!(remaining, Some(Duration::from_secs(2)));
+        assert_eq!(progress, Some(0.5));
+        assert_eq!(
+            rate_remaining_progress(0, Some(0), Duration::ZERO).2,
+            Some(1.0)
+        );
     }
-    id % 1_000_000
 }
@@ -1,4 +1,4 @@
-use super::structs::{DownloadProgress, ResolvedResource};
+use super::structs::{DownloadProgress, ResolvedResource, TaskId};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -22,23 +22,60 @@ pub enum TaskState {
     Failed,
 }
 
-#[derive(Clone)]
+/// 关闭下载器的方式
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ShutdownMode {
+    /// 下载完进行中的任务，不再开始排队中的任务
+    Drain,
+    /// 暂停所有任务并保存准确的下载进度，之后可以续传
+    Checkpoint,
+    /// 立即取消所有任务
+    Abort,
+}
+
+/// 已完成或已取消的任务在任务表中的保留策略，失败的任务总是保留以便续传
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Serialize, Deserialize)]
+pub enum RetentionPolicy {
+    /// 保留所有任务
+    #[default]
+    KeepAll,
+    /// 只保留最近结束的 n 个任务，0 表示结束后立即移除
+    KeepLast(usize),
+}
+
+/// 排队任务的调度策略
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Serialize, Deserialize)]
+pub enum SchedulePolicy {
+    /// 按入队顺序开始
+    Fifo,
+    /// 优先级高的先开始，优先级相同时按入队顺序
+    #[default]
+    Priority,
+    /// 文件小的先开始，大小在入队时通过HEAD请求获取，未知的排在最后
+    SmallestFirst,
+    /// 按域名轮流开始，避免同一站点的任务占满所有并发名额
+    RoundRobin,
+}
+
+#[derive(Debug, Clone)]
 pub enum ProgressEvent {
     Start {
-        task_id: u32,
-        total: u64,
+        task_id: TaskId,
+        total: Option<u64>,
+        /// 续传时已下载的字节数
+        offset: u64,
     },
     Update {
-        task_id: u32,
+        task_id: TaskId,
         progress: DownloadProgress,
     },
     Finish {
-        task_id: u32,
+        task_id: TaskId,
         finish: DownloadResult,
     },
     OperationResult {
         operation: OperationType,
-        task_id: u32,
+        task_id: TaskId,
         code: u32,
         message: String,
     },
@@ -74,18 +111,22 @@ pub enum OperationType {
     CancelAll,
 
     // 单任务操作
-    StartTask(u32),
-    PauseTask(u32),
-    ResumeTask(u32),
-    CancelTask(u32),
+    StartTask(TaskId),
+    PauseTask(TaskId),
+    ResumeTask(TaskId),
+    CancelTask(TaskId),
+    RemoveTask(TaskId),
+    ReprioritizeTask(TaskId),
 
     // 系统级操作
     ChangeConcurrency(u32),
+    /// 0 表示取消限速
     SetRateLimit(u64),
+    SetHostPolicy,
 
     // 下载结果
     Download,
-    DownloadTask(u32),
+    DownloadTask(TaskId),
 }
 
 impl OperationType {
@@ -103,18 +144,22 @@ impl OperationType {
             OperationType::SetRateLimit(_) => 9,
             OperationType::Download => 10,
             OperationType::DownloadTask(_) => 11,
+            OperationType::RemoveTask(_) => 12,
+            OperationType::ReprioritizeTask(_) => 13,
+            OperationType::SetHostPolicy => 14,
         }
     }
     pub fn is_global(&self) -> bool {
-        match self {
+        matches!(
+            self,
             OperationType::StartAll
-            | OperationType::PauseAll
-            | OperationType::ResumeAll
-            | OperationType::CancelAll
-            | OperationType::ChangeConcurrency(_)
-            | OperationType::SetRateLimit(_) => true,
-            _ => false,
-        }
+                | OperationType::PauseAll
+                | OperationType::ResumeAll
+                | OperationType::CancelAll
+                | OperationType::ChangeConcurrency(_)
+                | OperationType::SetRateLimit(_)
+                | OperationType::SetHostPolicy
+        )
     }
 }
 
@@ -131,8 +176,12 @@ impl std::fmt::Display for OperationType {
             OperationType::PauseTask(id) => write!(f, "Pause task {}", id),
             OperationType::ResumeTask(id) => write!(f, "Resume task {}", id),
             OperationType::CancelTask(id) => write!(f, "Cancel task {}", id),
+            OperationType::RemoveTask(id) => write!(f, "Remove task {}", id),
+            OperationType::ReprioritizeTask(id) => write!(f, "Reprioritize task {}", id),
             OperationType::ChangeConcurrency(n) => write!(f, "Change concurrency to {}", n),
+            OperationType::SetRateLimit(0) => write!(f, "Remove rate limit"),
             OperationType::SetRateLimit(n) => write!(f, "Set rate limit to {} B/s", n),
+            OperationType::SetHostPolicy => write!(f, "Set host policy"),
         }
     }
 }
@@ -1,32 +1,68 @@
 use super::algorithms::parse_content_disposition;
-use super::enums::{AuthMethod, FileChecksum};
+use super::enums::{AuthMethod, FileChecksum, RetentionPolicy, SchedulePolicy};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// 任务ID，由 Downloader 按顺序分配，在同一个下载器内唯一
+///
+/// 与资源自带的 ResolvedResource::id 无关，同一个URL加入两次会得到两个不同的ID
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    /// 与具体任务无关的操作结果（如整批下载完成）使用的ID
+    pub const NONE: TaskId = TaskId(0);
+
+    pub fn new(id: u64) -> Self {
+        Self(id)
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for TaskId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DownloadProgress {
+    /// 已下载字节数
     pub bytes_downloaded: u64,
-    pub total_bytes: u64,
-    pub rate: f64,
-    pub remaining_time: std::time::Duration,
-    pub progress_percentage: f64,
+    /// 总字节数（可能未知）
+    pub total_bytes: Option<u64>,
+    /// 实时下载速率（字节/秒）
+    pub current_rate: f64,
+    /// 平均下载速率（字节/秒）
+    pub average_rate: f64,
+    /// 已用时间
+    pub elapsed_time: std::time::Duration,
+    /// 预估剩余时间（当总大小未知时为None）
+    pub remaining_time: Option<std::time::Duration>,
+    /// 下载进度百分比（0.0-100.0，当总大小未知时为None）
+    pub progress_percentage: Option<f64>,
 }
 
-// pub struct DownloadProgress {
-//     /// 已下载字节数
-//     pub bytes_downloaded: u64,
-//     /// 总字节数（可能未知）
-//     pub total_bytes: Option<u64>,
-//     /// 实时下载速率（字节/秒）
-//     pub current_rate: f64,
-//     /// 平均下载速率（字节/秒）
-//     pub average_rate: f64,
-//     /// 已用时间
-//     pub elapsed_time: std::time::Duration,
-//     /// 预估剩余时间（当总大小未知时为None）
-//     pub remaining_time: Option<std::time::Duration>,
-//     /// 下载进度百分比（0.0-100.0）
-//     pub progress_percentage: Option<f64>,
-// }
+impl DownloadProgress {
+    pub fn new(total_bytes: Option<u64>) -> Self {
+        Self {
+            bytes_downloaded: 0,
+            total_bytes,
+            current_rate: 0.0,
+            average_rate: 0.0,
+            elapsed_time: std::time::Duration::ZERO,
+            remaining_time: None,
+            progress_percentage: total_bytes.map(|_| 0.0),
+        }
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DownloadOptions {
@@ -58,6 +94,21 @@ pub struct DownloadOptions {
     pub chunk_size: Option<u64>,
     /// 是否启用范围请求
     pub enable_range: bool,
+    /// 单个任务分段下载时的最大并发连接数
+    #[serde(default = "default_connections_per_task")]
+    pub connections_per_task: u32,
+    /// 暂停任务时保持HTTP连接并占用并发名额；默认断开连接、让出名额，恢复时通过范围请求续传
+    #[serde(default)]
+    pub keep_connection_on_pause: bool,
+    /// 排队任务的调度策略
+    #[serde(default)]
+    pub schedule_policy: SchedulePolicy,
+    /// 按主机的连接数限制与请求间隔
+    #[serde(default)]
+    pub host_policy: HostPolicy,
+    /// 已结束任务在任务表中的保留策略
+    #[serde(default)]
+    pub retention: RetentionPolicy,
 
     // 流量控制
     /// 全局速率限制（字节/秒）
@@ -78,6 +129,10 @@ pub struct DownloadOptions {
     pub progress_interval: u64,
 }
 
+fn default_connections_per_task() -> u32 {
+    4
+}
+
 impl Default for DownloadOptions {
     fn default() -> Self {
         Self {
@@ -92,6 +147,11 @@ impl Default for DownloadOptions {
             concurrency: 4,
             chunk_size: None,
             enable_range: true,
+            connections_per_task: default_connections_per_task(),
+            keep_connection_on_pause: false,
+            schedule_policy: SchedulePolicy::default(),
+            host_policy: HostPolicy::default(),
+            retention: RetentionPolicy::default(),
             rate_limit: None,
             per_connection_rate_limit: None,
             max_retries: 3,
@@ -124,6 +184,41 @@ impl DownloadOptions {
         self
     }
 
+    pub fn with_chunk_size(mut self, size: u64) -> Self {
+        self.chunk_size = Some(size);
+        self
+    }
+
+    pub fn with_enable_range(mut self, enable: bool) -> Self {
+        self.enable_range = enable;
+        self
+    }
+
+    pub fn with_connections_per_task(mut self, n: u32) -> Self {
+        self.connections_per_task = n;
+        self
+    }
+
+    pub fn with_keep_connection_on_pause(mut self, keep: bool) -> Self {
+        self.keep_connection_on_pause = keep;
+        self
+    }
+
+    pub fn with_schedule_policy(mut self, policy: SchedulePolicy) -> Self {
+        self.schedule_policy = policy;
+        self
+    }
+
+    pub fn with_retention(mut self, retention: RetentionPolicy) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    pub fn with_host_policy(mut self, policy: HostPolicy) -> Self {
+        self.host_policy = policy;
+        self
+    }
+
     pub fn with_header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
         self.headers.push((key.into(), value.into()));
         self
@@ -148,6 +243,26 @@ impl DownloadOptions {
         self.resume_download = resume;
         self
     }
+
+    pub fn with_max_retries(mut self, retries: u32) -> Self {
+        self.max_retries = retries;
+        self
+    }
+
+    pub fn with_timeout(mut self, seconds: u64) -> Self {
+        self.timeout = seconds;
+        self
+    }
+
+    pub fn with_user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    pub fn with_tls_verify(mut self, verify: bool) -> Self {
+        self.tls_verify = verify;
+        self
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -167,6 +282,10 @@ pub struct DownloadMeta {
     /// 从Content-Disposition解析的文件名
     pub suggested_filename: Option<String>,
 
+    /// 服务器是否支持字节范围请求（Accept-Ranges: bytes）
+    #[serde(default)]
+    pub accept_ranges: bool,
+
     /// 下载开始时间戳
     pub download_start: Option<DateTime<Utc>>,
 
@@ -201,16 +320,95 @@ impl DownloadMeta {
             .and_then(|v| v.to_str().ok())
             .and_then(parse_content_disposition);
 
+        let accept_ranges = headers
+            .get("Accept-Ranges")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|s| s.eq_ignore_ascii_case("bytes"));
+
         Self {
             content_type,
             etag,
             last_modified,
             expected_size: content_length,
             suggested_filename,
+            accept_ranges,
             download_start: Some(Utc::now()),
             checksum: None,
         }
     }
+
+    /// If-Range使用的校验值：优先使用强ETag，其次Last-Modified（弱ETag不能用于If-Range）
+    pub fn if_range(&self) -> Option<String> {
+        self.etag
+            .as_ref()
+            .filter(|etag| !etag.starts_with("W/"))
+            .or(self.last_modified.as_ref())
+            .cloned()
+    }
+
+    /// 与之前记录的ETag/Last-Modified比较，判断远程文件是否未变；没有可比较的值时视为未变
+    pub fn matches_validator(&self, etag: Option<&str>, last_modified: Option<&str>) -> bool {
+        match (self.etag.as_deref(), etag) {
+            (Some(current), Some(previous)) => current == previous,
+            _ => match (self.last_modified.as_deref(), last_modified) {
+                (Some(current), Some(previous)) => current == previous,
+                _ => true,
+            },
+        }
+    }
+}
+
+/// 分段下载中的一个字节范围（闭区间，与HTTP Range头一致）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Segment {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Segment {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end < self.start
+    }
+
+    pub fn range_header(&self) -> String {
+        format!("bytes={}-{}", self.start, self.end)
+    }
+}
+
+/// 分段的下载进度，记录在状态文件中
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentProgress {
+    pub start: u64,
+    pub end: u64,
+    /// 从 start 开始已写入的字节数
+    pub written: u64,
+}
+
+/// 状态文件中记录的认证方式，不含密码、令牌等凭据，续传时由解析器重新提供
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AuthRef {
+    Basic { username: String },
+    Bearer,
+    ApiKey { header: String },
+}
+
+impl AuthRef {
+    pub fn from_method(method: &AuthMethod) -> Option<Self> {
+        match method {
+            AuthMethod::None => None,
+            AuthMethod::Basic { username, .. } => Some(AuthRef::Basic {
+                username: username.clone(),
+            }),
+            AuthMethod::Bearer { .. } => Some(AuthRef::Bearer),
+            AuthMethod::ApiKey { header, .. } => Some(AuthRef::ApiKey {
+                header: header.clone(),
+            }),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -219,6 +417,55 @@ pub struct ResolvedResource {
     pub url: String,
     pub headers: Vec<(String, String)>,
     pub auth: Option<AuthMethod>,
+    /// 期望的文件校验值，下载完成后进行校验
+    #[serde(default)]
+    pub checksum: Option<FileChecksum>,
+    /// 优先级，数值越大越先开始（SchedulePolicy::Priority 等策略下生效）
+    #[serde(default)]
+    pub priority: i32,
+}
+
+/// 按主机的连接限制，主机名与 organize_by_domain 使用的一致
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HostPolicy {
+    /// 每个主机的最大并发连接数，None表示不限制
+    pub max_connections: Option<u32>,
+    /// 指定主机的最大并发连接数，优先于 max_connections
+    #[serde(default)]
+    pub overrides: HashMap<String, u32>,
+    /// 对同一主机两次请求之间的最小间隔（毫秒）
+    #[serde(default)]
+    pub min_delay: u64,
+}
+
+impl HostPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_connections(mut self, n: u32) -> Self {
+        self.max_connections = Some(n);
+        self
+    }
+
+    pub fn with_host_limit(mut self, host: impl Into<String>, n: u32) -> Self {
+        self.overrides.insert(host.into(), n);
+        self
+    }
+
+    pub fn with_min_delay(mut self, millis: u64) -> Self {
+        self.min_delay = millis;
+        self
+    }
+
+    /// 指定主机的最大并发连接数（至少为1），None表示不限制
+    pub fn limit_for(&self, host: &str) -> Option<u32> {
+        self.overrides
+            .get(host)
+            .copied()
+            .or(self.max_connections)
+            .map(|n| n.max(1))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -295,3 +542,14 @@ impl PathPolicy {
         self
     }
 }
+
+/// 关闭下载器的结果，共享下载的任务各自列出
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShutdownSummary {
+    /// 关闭期间下载完成的任务
+    pub completed: Vec<TaskId>,
+    /// 未完成但进度已保存、之后可以用 resume_session 续传的任务
+    pub persisted: Vec<TaskId>,
+    /// 被丢弃的任务：尚未开始的排队任务与被取消的任务
+    pub dropped: Vec<TaskId>,
+}
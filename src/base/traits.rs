@@ -1,13 +1,15 @@
 use super::enums::{DownloadResource, DownloadResult, OperationType};
-use super::structs::{DownloadProgress, ResolvedResource};
+use super::structs::{DownloadProgress, ResolvedResource, TaskId};
 use crate::error::Result;
+use crate::task::{PersistentState, TaskStateRecord};
 use async_trait::async_trait;
 
 #[async_trait]
 pub trait ProgressReporter {
-    async fn start_task(&self, task_id: u32, total: u64) -> Result<()>;
-    async fn update_progress(&self, task_id: u32, progress: &DownloadProgress) -> Result<()>;
-    async fn finish_task(&self, task_id: u32, result: DownloadResult) -> Result<()>;
+    /// total 为 None 表示总大小未知（如分块传输编码）；offset 为续传时已下载的字节数
+    async fn start_task(&self, task_id: TaskId, total: Option<u64>, offset: u64) -> Result<()>;
+    async fn update_progress(&self, task_id: TaskId, progress: &DownloadProgress) -> Result<()>;
+    async fn finish_task(&self, task_id: TaskId, result: DownloadResult) -> Result<()>;
 }
 
 #[async_trait]
@@ -15,7 +17,7 @@ pub trait ResultReporter {
     async fn operation_result(
         &self,
         operation: OperationType,
-        task_id: u32,
+        task_id: TaskId,
         code: u32,
         message: String,
     ) -> Result<()>;
@@ -28,3 +30,30 @@ impl<T: ProgressReporter + ResultReporter + Send + Sync> CombinedReporter for T
 pub trait ResourceResolver: Send + Sync {
     async fn resolve(&self, resource: &DownloadResource) -> Result<ResolvedResource>;
 }
+
+/// 下载状态的持久化存储，记录按内容键（TaskStateRecord::key）区分，同一资源只保留一条
+#[async_trait]
+pub trait StateStore: Send + Sync {
+    /// 载入保存的状态，从未保存过时返回None
+    async fn load(&self) -> Result<Option<PersistentState>>;
+    /// 写入一条记录，已有同一资源的记录时替换
+    async fn upsert_task(&self, record: &TaskStateRecord) -> Result<()>;
+    async fn remove_task(&self, key: &str) -> Result<()>;
+    async fn list(&self) -> Result<Vec<TaskStateRecord>>;
+
+    /// 写入多条记录，默认逐条写入
+    async fn upsert_all(&self, records: &[TaskStateRecord]) -> Result<()> {
+        for record in records {
+            self.upsert_task(record).await?;
+        }
+        Ok(())
+    }
+
+    /// 删除所有记录，所有任务都已完成时调用
+    async fn clear(&self) -> Result<()> {
+        for record in self.list().await? {
+            self.remove_task(&record.content_key()).await?;
+        }
+        Ok(())
+    }
+}
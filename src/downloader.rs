@@ -1,95 +1,345 @@
-use crate::base::algorithms::rate_remaining_progress;
 use crate::base::algorithms::{
-    auto_filename, custom_directory, custom_filename, generate_task_id, organize_by_domain,
-    organize_by_type,
+    auto_filename, backoff_delay, content_key, contiguous_prefix, custom_directory,
+    custom_filename, organize_by_domain, organize_by_type, parse_retry_after, split_segments,
 };
+use crate::base::algorithms::{progress, rate, rate_remaining_progress, remaining_time};
 use crate::base::enums::{
-    AuthMethod, DownloadResource, DownloadResult, DownloaderState, OperationType, TaskState,
+    AuthMethod, DownloadResource, DownloadResult, DownloaderState, OperationType, SchedulePolicy,
+    ShutdownMode, TaskState,
+};
+use crate::base::structs::{
+    AuthRef, DownloadMeta, DownloadOptions, DownloadProgress, HostPolicy, ResolvedResource,
+    Segment, ShutdownSummary, TaskId,
+};
+use crate::base::traits::{CombinedReporter, ResourceResolver, StateStore};
+use crate::error::{Error, ErrorKind, Result};
+use crate::hash::{ChecksumVerifier, verify_file};
+use crate::journal::StateJournal;
+use crate::limiter::{HostLimiter, RateLimiter};
+use crate::part::{PartFile, PartMeta};
+use crate::registry::TaskRegistry;
+use crate::scheduler::{QueuedTask, Scheduler, Slot};
+use crate::stores::json::JsonStateStore;
+use crate::task::{
+    DownloadTask, HandleSender, PersistentState, TaskControl, TaskHandle, TaskStateRecord,
 };
-use crate::base::structs::{DownloadMeta, DownloadOptions, DownloadProgress, ResolvedResource};
-use crate::base::traits::{CombinedReporter, ResourceResolver};
-use crate::error::Result;
-use crate::task::{DownloadTask, PersistentState, TaskStateRecord};
 use crate::template::{TemplateContext, TemplateRenderer};
-use futures::stream::StreamExt;
+use futures::TryFutureExt;
+use futures::stream::{StreamExt, TryStreamExt};
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::io::AsyncWriteExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+
+/// 下载过程中保存状态文件的间隔
+const SAVE_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(1);
+/// 首次重试前的基础等待时间
+const RETRY_BASE_DELAY: tokio::time::Duration = tokio::time::Duration::from_millis(500);
+/// 重试等待时间的上限
+const RETRY_MAX_DELAY: tokio::time::Duration = tokio::time::Duration::from_secs(30);
+/// 计算实时速率的采样窗口
+const RATE_WINDOW: tokio::time::Duration = tokio::time::Duration::from_secs(1);
+/// 遵守 Retry-After 时的最长等待时间
+const MAX_RETRY_AFTER: tokio::time::Duration = tokio::time::Duration::from_secs(300);
+/// 小文件优先的策略下，同时发出的HEAD请求数
+const PROBE_CONCURRENCY: usize = 16;
+
+/// 单个任务的传输上下文，在该任务的所有连接之间共享
+struct Transfer {
+    task: DownloadTask,
+    /// 总大小，服务器未提供Content-Length（如分块传输编码）时为None
+    total_size: Option<u64>,
+    /// 本次开始时磁盘上已有的字节数，不计入速率
+    initial_size: u64,
+    /// 范围请求附带的If-Range校验值，远程文件变化时服务器会返回完整内容
+    if_range: Option<String>,
+    /// 分段下载中发现远程文件已变化时，变化后的文件的响应头信息
+    remote_changed: std::sync::Mutex<Option<DownloadMeta>>,
+    /// 暂停时断开连接并让出并发名额
+    release_on_pause: bool,
+    /// 写入中的临时文件及其续传元数据
+    part: PartFile,
+    part_meta: PartMeta,
+    downloaded: AtomicU64,
+    start_time: tokio::time::Instant,
+    rate_sample: std::sync::Mutex<RateSample>,
+    verifier: Option<std::sync::Mutex<ChecksumVerifier>>,
+}
+
+/// 实时速率采样：上一个窗口结束时的时间与字节数
+struct RateSample {
+    at: tokio::time::Instant,
+    bytes: u64,
+    rate: Option<f64>,
+}
+
+impl Transfer {
+    /// 根据已下载字节数计算进度，实时速率按 RATE_WINDOW 采样，首个窗口内使用平均速率
+    fn progress(&self, downloaded: u64) -> DownloadProgress {
+        let elapsed = self.start_time.elapsed();
+        let average_rate = rate(downloaded.saturating_sub(self.initial_size), elapsed);
+
+        let current_rate = {
+            let mut sample = self.rate_sample.lock().unwrap();
+            let window = sample.at.elapsed();
+            if window >= RATE_WINDOW {
+                sample.rate = Some(rate(downloaded.saturating_sub(sample.bytes), window));
+                sample.at = tokio::time::Instant::now();
+                sample.bytes = downloaded;
+            }
+            sample.rate.unwrap_or(average_rate)
+        };
+
+        DownloadProgress {
+            bytes_downloaded: downloaded,
+            total_bytes: self.total_size,
+            current_rate,
+            average_rate,
+            elapsed_time: elapsed,
+            remaining_time: remaining_time(downloaded, self.total_size, current_rate),
+            progress_percentage: progress(downloaded, self.total_size).map(|p| p * 100.0),
+        }
+    }
+}
+
+/// 每个数据块处理前的状态检查结果
+enum TaskFlow {
+    Continue,
+    Break,
+    Canceled,
+    Stopped,
+    /// 任务已暂停，需要断开连接并让出并发名额
+    Paused,
+}
 
+/// 下载器，克隆后共享同一组任务与状态
 #[derive(Clone)]
 pub struct Downloader {
-    client: reqwest::Client,
+    inner: Arc<DownloaderInner>,
+}
+
+/// 所有 Downloader 克隆共享的状态
+pub struct DownloaderInner {
+    client: Arc<std::sync::RwLock<reqwest::Client>>,
     options: Arc<RwLock<DownloadOptions>>,
     pub state: Arc<RwLock<DownloaderState>>,
-    pub tasks: Arc<RwLock<Vec<DownloadTask>>>,
+    pub tasks: Arc<TaskRegistry>,
     resolver: Arc<Box<dyn ResourceResolver>>,
     reporter: Arc<Box<dyn CombinedReporter>>,
-    state_notifier: tokio::sync::broadcast::Sender<DownloaderState>,
-    cancel_token: tokio_util::sync::CancellationToken,
+    /// 下载器状态变化时通知，暂停中的任务在此等待
+    state_notifier: tokio::sync::watch::Sender<DownloaderState>,
+    /// 停止时取消，各下载的取消令牌都是它的子令牌
+    cancel_token: Arc<std::sync::Mutex<CancellationToken>>,
+    rate_limiter: Arc<RateLimiter>,
+    host_limiter: Arc<HostLimiter>,
+    /// 并发名额，每个下载中的任务占用一个，暂停的任务会让出名额
+    scheduler: Arc<Scheduler>,
+    /// 运行中的分派循环的停止令牌，关闭或释放下载器时取消
+    dispatcher: Arc<std::sync::Mutex<Option<CancellationToken>>>,
+    /// 下一个分配的任务ID，0 保留给全局操作
+    next_id: Arc<AtomicU64>,
+    /// 排队中与下载中的资源，按内容键索引，相同的资源共享一次下载
+    shared: Arc<std::sync::Mutex<HashMap<String, SharedDownload>>>,
+    /// 保存状态的存储与保存请求的合并
+    store: Arc<Box<dyn StateStore>>,
+    journal: Arc<StateJournal>,
+    /// 正在关闭时的方式，此时不再接受新任务
+    shutdown: Arc<std::sync::Mutex<Option<ShutdownMode>>>,
+    /// 已开始的下载的取消令牌与任务句柄
+    running: Arc<std::sync::Mutex<HashMap<TaskId, TaskControl>>>,
+    /// 由 resume_session 恢复的资源的内容键，不受 resume_download 选项限制
+    resumed: Arc<std::sync::Mutex<HashSet<String>>>,
+    /// 尚未收到结果的任务句柄
+    handles: Arc<std::sync::Mutex<HashMap<TaskId, HandleSender>>>,
+}
+
+/// 多个任务共享的一次下载
+struct SharedDownload {
+    /// 实际执行下载的任务
+    task_id: TaskId,
+    /// 等待结果的任务，包括执行下载的任务本身（它被移除后除外）
+    waiters: Vec<TaskId>,
+    /// 已开始时为资源的总大小与开始位置，之后加入的任务立即收到 start_task
+    started: Option<(Option<u64>, u64)>,
+}
+
+impl std::ops::Deref for Downloader {
+    type Target = DownloaderInner;
+
+    fn deref(&self) -> &DownloaderInner {
+        &self.inner
+    }
+}
+
+impl Drop for DownloaderInner {
+    fn drop(&mut self) {
+        if let Some(stop) = self.dispatcher.lock().unwrap().take() {
+            stop.cancel();
+        }
+    }
 }
 
 impl Downloader {
+    /// 创建下载器，DownloadOptions 中的网络配置无效（如代理地址错误）时会panic，
+    /// 需要处理错误时请使用 [`Downloader::try_new`]
+    ///
+    /// store 保存下载状态以便续传，使用 `JsonStateStore::in_dir(&options.save_path)` 时
+    /// 与之前一样保存在 `<save_path>/downloading.json`
     pub fn new(
         options: DownloadOptions,
         resolver: Box<dyn ResourceResolver>,
         reporter: Box<dyn CombinedReporter>,
+        store: Box<dyn StateStore>,
     ) -> Self {
-        let client = reqwest::ClientBuilder::new()
-            .user_agent("vielpork")
-            .default_headers(reqwest::header::HeaderMap::new())
-            .build()
-            .unwrap();
-        Self {
-            client,
-            options: Arc::new(RwLock::new(options)),
-            state: Arc::new(RwLock::new(DownloaderState::default())),
-            tasks: Arc::new(RwLock::new(Vec::new())),
-            resolver: Arc::new(resolver),
-            reporter: Arc::new(reporter),
-            state_notifier: tokio::sync::broadcast::channel(128).0,
-            cancel_token: tokio_util::sync::CancellationToken::new(),
-        }
+        Self::try_new(options, resolver, reporter, store).expect("Invalid network options")
+    }
+
+    pub fn try_new(
+        options: DownloadOptions,
+        resolver: Box<dyn ResourceResolver>,
+        reporter: Box<dyn CombinedReporter>,
+        store: Box<dyn StateStore>,
+    ) -> Result<Self> {
+        let client = build_client(&options)?;
+        let rate_limiter = Arc::new(RateLimiter::new(options.rate_limit));
+        let host_limiter = Arc::new(HostLimiter::new(options.host_policy.clone()));
+        let scheduler = Arc::new(Scheduler::new(options.concurrency as usize));
+        scheduler.set_policy(options.schedule_policy);
+        Ok(Self {
+            inner: Arc::new(DownloaderInner {
+                client: Arc::new(std::sync::RwLock::new(client)),
+                options: Arc::new(RwLock::new(options)),
+                state: Arc::new(RwLock::new(DownloaderState::default())),
+                tasks: Arc::new(TaskRegistry::new()),
+                resolver: Arc::new(resolver),
+                reporter: Arc::new(reporter),
+                state_notifier: tokio::sync::watch::Sender::new(DownloaderState::default()),
+                cancel_token: Arc::new(std::sync::Mutex::new(CancellationToken::new())),
+                rate_limiter,
+                host_limiter,
+                scheduler,
+                dispatcher: Arc::new(std::sync::Mutex::new(None)),
+                next_id: Arc::new(AtomicU64::new(1)),
+                shared: Arc::new(std::sync::Mutex::new(HashMap::new())),
+                store: Arc::new(store),
+                journal: Arc::new(StateJournal::new()),
+                shutdown: Arc::new(std::sync::Mutex::new(None)),
+                running: Arc::new(std::sync::Mutex::new(HashMap::new())),
+                resumed: Arc::new(std::sync::Mutex::new(HashSet::new())),
+                handles: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            }),
+        })
     }
 
     pub async fn transition_state(&self, new_state: DownloaderState) -> Result<()> {
         let mut current = self.state.write().await;
 
-        let valid = match (*current, new_state) {
-            (DownloaderState::Idle, DownloaderState::Idle) => true,
-            (DownloaderState::Idle, DownloaderState::Running) => true,
-            (DownloaderState::Running, DownloaderState::Suspended) => true,
-            (DownloaderState::Suspended, DownloaderState::Running) => true,
-            (DownloaderState::Stopped, DownloaderState::Idle) => true,
-            (_, DownloaderState::Stopped) => true,
-            _ => false,
-        };
+        let valid = matches!(
+            (*current, new_state),
+            (DownloaderState::Idle, DownloaderState::Idle)
+                | (DownloaderState::Idle, DownloaderState::Running)
+                | (DownloaderState::Running, DownloaderState::Idle)
+                | (DownloaderState::Running, DownloaderState::Suspended)
+                | (DownloaderState::Suspended, DownloaderState::Running)
+                | (DownloaderState::Stopped, DownloaderState::Idle)
+                | (_, DownloaderState::Stopped)
+        );
 
         if valid {
             *current = new_state;
-            self.state_notifier.send(new_state.clone()).ok();
+            self.state_notifier.send_replace(new_state);
             Ok(())
         } else {
-            Err(format!("Cannot transition from {:?} to {:?}", *current, new_state).into())
+            Err(ErrorKind::InvalidStateTransition {
+                from: format!("{:?}", *current),
+                to: format!("{:?}", new_state),
+            }
+            .into())
         }
     }
-    pub async fn update_options(&self, options: DownloadOptions) -> Self {
+    /// 更新配置并重建HTTP客户端
+    ///
+    /// 进行中的请求继续使用旧客户端，之后发起的请求（包括重试和分段）使用新客户端
+    pub async fn update_options(&self, options: DownloadOptions) -> Result<Self> {
+        let client = build_client(&options)?;
+        *self.client.write().unwrap() = client;
+        self.rate_limiter.set_rate(options.rate_limit);
+        self.scheduler.set_limit(options.concurrency as usize);
+        self.scheduler.set_policy(options.schedule_policy);
+        self.host_limiter.set_policy(options.host_policy.clone());
         *self.options.write().await = options;
-        self.clone()
+        Ok(self.clone())
+    }
+
+    /// 调整全局限速（字节/秒），对所有任务立即生效，None 表示不限速
+    pub async fn set_rate_limit(&self, limit: Option<u64>) -> Result<()> {
+        self.options.write().await.rate_limit = limit;
+        self.rate_limiter.set_rate(limit);
+
+        let message = match limit {
+            Some(limit) => format!("Rate limit set to {} B/s", limit),
+            None => "Rate limit removed".to_string(),
+        };
+        self.reporter
+            .operation_result(
+                OperationType::SetRateLimit(limit.unwrap_or(0)),
+                TaskId::NONE,
+                200,
+                message,
+            )
+            .await
+    }
+    /// 调整按主机的连接数限制与请求间隔，对之后的请求立即生效
+    pub async fn set_host_policy(&self, policy: HostPolicy) -> Result<()> {
+        self.options.write().await.host_policy = policy.clone();
+        self.host_limiter.set_policy(policy);
+        self.reporter
+            .operation_result(
+                OperationType::SetHostPolicy,
+                TaskId::NONE,
+                200,
+                "Host policy updated".to_string(),
+            )
+            .await
+    }
+
+    /// 调整同时下载的任务数，对正在进行的下载立即生效
+    ///
+    /// 缩小时不会中断正在下载的任务，超出的任务在它们结束后继续排队
+    pub async fn set_concurrency(&self, concurrency: u32) -> Result<()> {
+        let result = if concurrency == 0 {
+            Err(ErrorKind::InvalidPolicy("concurrency must be at least 1".to_string()).into())
+        } else {
+            self.options.write().await.concurrency = concurrency;
+            self.scheduler.set_limit(concurrency as usize);
+            Ok(())
+        };
+        self.report_operation(
+            OperationType::ChangeConcurrency(concurrency),
+            TaskId::NONE,
+            &result,
+            &format!("Concurrency set to {}", concurrency),
+        )
+        .await;
+        result
     }
+
     pub async fn get_options(&self) -> DownloadOptions {
         self.options.read().await.clone()
     }
     pub async fn get_tasks(&self) -> Vec<DownloadTask> {
-        self.tasks.read().await.clone()
+        self.tasks.snapshot()
     }
     pub async fn get_downloading_tasks(&self) -> Vec<DownloadTask> {
-        let tasks = self.tasks.read().await;
-        futures::stream::iter(tasks.iter())
+        futures::stream::iter(self.tasks.snapshot())
             .filter_map(|t| async move {
                 let state = t.state.read().await;
                 if *state == TaskState::Downloading {
-                    Some(t.clone())
+                    drop(state);
+                    Some(t)
                 } else {
                     None
                 }
@@ -105,71 +355,140 @@ impl Downloader {
     ) -> Vec<DownloadResource> {
         let mut optimized = Vec::new();
         let resolver = self.resolver.clone();
-        // 先解析所有的资源，然后对比resolved_resources的url是否在state中且已完成或取消
-        let resolved_resources = futures::future::join_all(
-            resources
-                .into_iter()
-                .map(async |resource| resolver.resolve(&resource).await),
-        )
-        .await;
-
-        for resolved in resolved_resources.iter() {
-            match resolved {
-                Ok(resolved) => {
-                    let task = state.tasks.iter().find(|t| t.url == resolved.url);
-                    match task {
-                        Some(t) => {
-                            if t.state != TaskState::Completed || t.state != TaskState::Canceled {
-                                optimized.push(DownloadResource::Resolved(resolved.clone()));
-                            }
-                        }
-                        None => {
-                            optimized.push(DownloadResource::Resolved(resolved.clone()));
-                        }
-                    }
+        // 先解析所有的资源，然后按内容键查找上次会话中已完成的记录
+        let resolved_resources =
+            futures::future::join_all(resources.into_iter().map(async |resource| {
+                let resolved = resolver.resolve(&resource).await;
+                (resource, resolved)
+            }))
+            .await;
+
+        for (resource, resolved) in resolved_resources {
+            // 解析失败的资源保留原样，错误在下载时报告
+            let Ok(resolved) = resolved else {
+                optimized.push(resource);
+                continue;
+            };
+            let key = content_key(&resolved.url);
+            let completed = state
+                .tasks
+                .iter()
+                .rev()
+                .find(|t| t.state == TaskState::Completed && t.content_key() == key);
+            if let Some(record) = completed {
+                if self.skip_completed(record, &resolved).await {
+                    continue;
                 }
-                Err(_) => {}
             }
+            optimized.push(DownloadResource::Resolved(resolved));
         }
 
         optimized
     }
-    pub async fn start(&self, resources: Vec<DownloadResource>) -> Result<()> {
-        self.init().await?;
 
-        let options = self.get_options().await;
-        let save_path = &options.save_path;
-        let state_path = PathBuf::from(save_path).join("downloading.json");
-        let optimized_resources: Vec<DownloadResource>;
-        if state_path.exists() {
-            let contents = tokio::fs::read_to_string(&state_path).await?;
-            let state: PersistentState = serde_json::from_str(&contents)?;
-            optimized_resources = self.optimize_resources(resources, state).await;
-        } else {
-            optimized_resources = resources;
+    /// 上次会话已完成的资源：磁盘上的文件大小一致且通过校验时跳过，并报告是否跳过
+    async fn skip_completed(&self, record: &TaskStateRecord, resolved: &ResolvedResource) -> bool {
+        let size = tokio::fs::metadata(&record.file_path)
+            .await
+            .ok()
+            .map(|metadata| metadata.len());
+        let problem = match size {
+            None => Some("the file is missing".to_string()),
+            Some(size) if record.total_bytes.is_some_and(|total| total != size) => Some(format!(
+                "the file has {} of {} bytes",
+                size,
+                record.total_bytes.unwrap_or_default()
+            )),
+            Some(_) => match &resolved.checksum {
+                Some(checksum) => verify_file(&record.file_path, checksum)
+                    .await
+                    .err()
+                    .map(|e| e.to_string()),
+                None => None,
+            },
+        };
+
+        if let Some(problem) = problem {
+            self.reporter
+                .operation_result(
+                    OperationType::Download,
+                    TaskId::NONE,
+                    200,
+                    format!(
+                        "{} was completed in a previous session but {}, downloading again",
+                        resolved.url, problem
+                    ),
+                )
+                .await
+                .ok();
+            return false;
         }
 
-        self.transition_state(DownloaderState::Running).await?;
+        let task_id = self.allocate_id();
+        let size = size.unwrap_or_default();
+        self.reporter
+            .operation_result(
+                OperationType::DownloadTask(task_id),
+                task_id,
+                200,
+                format!(
+                    "Skipped, already downloaded to {}",
+                    record.file_path.display()
+                ),
+            )
+            .await
+            .ok();
+        self.reporter
+            .start_task(task_id, Some(size), size)
+            .await
+            .ok();
+        self.report_finish(
+            task_id,
+            DownloadResult::Success {
+                path: record.file_path.clone(),
+                size,
+                duration: tokio::time::Duration::from_secs(0),
+            },
+        )
+        .await
+        .ok();
+        true
+    }
+    /// 将资源加入下载队列后立即返回；下载器正在运行时，这些资源会加入当前队列
+    pub async fn start(&self, resources: Vec<DownloadResource>) -> Result<()> {
+        let resources = self.prepare(resources).await?;
+        self.enqueue_all(resources).await
+    }
+
+    /// 与 start 相同，但会等待队列中的所有任务结束后才返回
+    pub async fn run(&self, resources: Vec<DownloadResource>) -> Result<()> {
+        self.start(resources).await?;
+        self.wait_idle().await;
+        Ok(())
+    }
 
-        let downloader = self.clone();
-        println!("Downloading {} resources", optimized_resources.len());
+    async fn prepare(&self, resources: Vec<DownloadResource>) -> Result<Vec<DownloadResource>> {
+        // 正在运行时直接加入队列，上次会话的记录已经载入过了
+        if !matches!(
+            *self.state.read().await,
+            DownloaderState::Idle | DownloaderState::Stopped
+        ) {
+            return Ok(resources);
+        }
+        self.init().await?;
 
-        let reporter = self.reporter.clone();
-        tokio::spawn(async move {
-            if let Err(e) = downloader.download_multi(optimized_resources).await {
-                reporter
-                    .operation_result(
-                        OperationType::Download,
-                        0,
-                        500,
-                        format!("Download failed: {}", e),
-                    )
-                    .await
-                    .ok();
+        let optimized_resources: Vec<DownloadResource> = match self.store.load().await? {
+            Some(state) => {
+                // 载入上次的任务记录，续传时用其中的ETag/Last-Modified校验远程文件
+                self.load_state(state.clone()).await?;
+                self.optimize_resources(resources, state).await
             }
-        });
+            None => resources,
+        };
 
-        Ok(())
+        println!("Downloading {} resources", optimized_resources.len());
+
+        Ok(optimized_resources)
     }
 
     pub async fn pause(&self) -> Result<()> {
@@ -178,13 +497,117 @@ impl Downloader {
     pub async fn resume(&self) -> Result<()> {
         self.transition_state(DownloaderState::Running).await
     }
+    /// 停止所有下载，等到它们释放文件与连接后返回
     pub async fn stop(&self) -> Result<()> {
-        self.tasks.write().await.clear();
-        self.transition_state(DownloaderState::Stopped).await
+        self.tasks.clear();
+        self.transition_state(DownloaderState::Stopped).await?;
+        self.cancel_token.lock().unwrap().cancel();
+        let running: Vec<TaskControl> = self.running.lock().unwrap().values().cloned().collect();
+        for control in running {
+            control.join().await;
+        }
+        Ok(())
     }
     pub async fn init(&self) -> Result<()> {
-        self.tasks.write().await.clear();
-        self.transition_state(DownloaderState::Idle).await
+        self.tasks.clear();
+        self.transition_state(DownloaderState::Idle).await?;
+        // 停止时取消的令牌不能再用于新的下载
+        let mut cancel_token = self.cancel_token.lock().unwrap();
+        if cancel_token.is_cancelled() {
+            *cancel_token = CancellationToken::new();
+        }
+        *self.shutdown.lock().unwrap() = None;
+        Ok(())
+    }
+
+    /// 关闭下载器，返回时所有任务都已释放文件与连接，状态已写入存储
+    ///
+    /// 排队中的任务不会再开始。Drain 等待进行中的任务完成，被单独暂停的任务按 Checkpoint 处理；
+    /// Checkpoint 暂停所有任务并保存准确的进度；Abort 取消所有任务。
+    /// 再次调用可以改用更快的方式（如 Drain 期间改为 Abort）
+    pub async fn shutdown(&self, mode: ShutdownMode) -> Result<ShutdownSummary> {
+        *self.shutdown.lock().unwrap() = Some(mode);
+        let mut summary = ShutdownSummary::default();
+
+        for task_id in self.scheduler.clear() {
+            summary
+                .dropped
+                .extend(self.take_waiters(task_id).unwrap_or_else(|| vec![task_id]));
+        }
+        self.close_handles(&summary.dropped);
+
+        // 进行中的下载及共享它们的任务
+        let running: Vec<(TaskId, TaskControl)> = self
+            .running
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(task_id, control)| (*task_id, control.clone()))
+            .collect();
+        let waiters: Vec<(TaskId, Vec<TaskId>)> = {
+            let shared = self.shared.lock().unwrap();
+            running
+                .iter()
+                .map(|(task_id, _)| {
+                    let waiters = shared
+                        .values()
+                        .find(|d| d.task_id == *task_id)
+                        .map_or_else(|| vec![*task_id], |d| d.waiters.clone());
+                    (*task_id, waiters)
+                })
+                .collect()
+        };
+
+        match mode {
+            ShutdownMode::Drain => {
+                if *self.state.read().await == DownloaderState::Suspended {
+                    self.transition_state(DownloaderState::Running).await?;
+                }
+                // 只有被单独暂停的任务按 Checkpoint 处理，因挂起而暂停的任务继续下载
+                for (task_id, control) in running.iter() {
+                    let paused = match self.find_task(*task_id).await {
+                        Some(task) => {
+                            task.end_suspend().await?;
+                            *task.state.read().await == TaskState::Paused
+                        }
+                        None => false,
+                    };
+                    if paused {
+                        control.cancel_token.cancel();
+                    }
+                }
+            }
+            ShutdownMode::Checkpoint | ShutdownMode::Abort => {
+                self.cancel_token.lock().unwrap().cancel();
+            }
+        }
+        for (_, control) in running.iter() {
+            control.join().await;
+        }
+
+        if self.scheduler.is_idle() {
+            self.scheduler.publish_idle();
+        }
+        // 不再接受新任务，结束分派循环
+        if let Some(stop) = self.dispatcher.lock().unwrap().as_ref() {
+            stop.cancel();
+        }
+        self.transition_state(DownloaderState::Stopped).await?;
+        self.persist_tasks().await?;
+
+        for (task_id, waiters) in waiters {
+            let state = match self.find_task(task_id).await {
+                Some(task) => Some(*task.state.read().await),
+                None => None,
+            };
+            let list = match state {
+                Some(TaskState::Completed) => &mut summary.completed,
+                Some(TaskState::Canceled) | None => &mut summary.dropped,
+                Some(_) => &mut summary.persisted,
+            };
+            list.extend(waiters);
+        }
+        Ok(summary)
     }
 
     async fn generate_path(
@@ -218,7 +641,11 @@ impl Downloader {
                 .await?
             }
             _ => {
-                return Err("Invalid naming policy".into());
+                return Err(ErrorKind::InvalidPolicy(format!(
+                    "naming {}",
+                    options.path_policy.naming
+                ))
+                .into());
             }
         };
 
@@ -243,617 +670,2022 @@ impl Downloader {
                 custom_directory(dir_template, &context, &TemplateRenderer::new()).await?
             }
             _ => {
-                return Err("Invalid organization policy".into());
+                return Err(ErrorKind::InvalidPolicy(format!(
+                    "organization {}",
+                    options.path_policy.organization
+                ))
+                .into());
             }
         };
 
-        // 步骤3：构建完整路径
-        let mut full_path = base_dir.join(subdir).join(filename);
-
-        // 步骤4：处理路径冲突
-        full_path = self.handle_conflict(full_path).await?;
+        // 步骤3：构建完整路径（路径冲突在下载完成、重命名临时文件时处理）
+        let full_path = base_dir.join(subdir).join(filename);
 
         Ok(full_path)
     }
 
-    async fn handle_conflict(&self, mut path: PathBuf) -> Result<PathBuf> {
-        let mut counter = 1;
-        let original_path = path.clone();
-        let options = self.get_options().await;
-
-        while path.exists() {
-            match options.path_policy.conflict.as_str() {
-                "overwrite" => {
-                    break;
-                }
-                "rename" => {
-                    let stem = original_path
-                        .file_stem()
-                        .unwrap_or_default()
-                        .to_str()
-                        .unwrap_or_default();
-                    let ext = original_path
-                        .extension()
-                        .map(|e| format!(".{}", e.to_str().unwrap_or_default()))
-                        .unwrap_or_default();
-
-                    path.set_file_name(format!("{}_{}{}", stem, counter, ext));
-                    counter += 1;
-                }
-                "error" => {
-                    return Err("File already exists".into());
-                }
-                _ => {
-                    return Err("Invalid conflict policy".into());
-                }
-            }
-        }
-        Ok(path)
-    }
+    /// 将一批资源加入队列，并等待队列中的所有任务结束
     pub async fn download_multi(&self, resources: Vec<DownloadResource>) -> Result<()> {
+        self.enqueue_all(resources).await?;
+        self.wait_idle().await;
+        Ok(())
+    }
+
+    async fn enqueue_all(&self, resources: Vec<DownloadResource>) -> Result<()> {
         let options = self.get_options().await;
         if options.create_dirs {
             tokio::fs::create_dir_all(&options.save_path).await?;
         }
-        let concurrency_limit = options.concurrency as usize;
-
-        let tasks =
-            resources
-                .into_iter()
-                .map(async |resource| match self.download_task(resource).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        self.reporter
-                            .operation_result(
-                                OperationType::Download,
-                                0,
-                                500,
-                                format!("Failed to download resource: {}", e),
-                            )
-                            .await
-                            .ok();
-                    }
-                });
 
-        let downloads = futures::stream::iter(tasks)
-            .buffer_unordered(concurrency_limit)
-            .collect::<Vec<()>>();
-
-        downloads.await;
-
-        tokio::fs::remove_file(PathBuf::from(&options.save_path).join("downloading.json")).await?;
-
-        self.reporter
-            .operation_result(
-                OperationType::Download,
-                0,
-                200,
-                "All Tasks Completed".to_string(),
-            )
-            .await?;
+        if resources.is_empty() && self.scheduler.is_idle() {
+            // 没有需要下载的资源，直接结束这一批
+            self.on_idle().await;
+            return Ok(());
+        }
 
+        let entries: Vec<QueuedTask> = futures::stream::iter(resources)
+            .map(|resource| {
+                let priority = resource_priority(&resource);
+                self.queue_entry(resource, priority)
+            })
+            .buffered(PROBE_CONCURRENCY)
+            .collect()
+            .await;
+        self.push_entries(entries).await?;
         Ok(())
     }
 
-    async fn download_task(&self, resource: DownloadResource) -> Result<()> {
-        let global_state = self.state.read().await;
-        if *global_state == DownloaderState::Stopped {
-            return Ok(());
-        }
-        drop(global_state);
-
-        let save_interval = tokio::time::Duration::from_secs(1);
-        let mut last_save = tokio::time::Instant::now();
+    /// 将资源加入下载队列，有空闲的并发名额时立即开始
+    ///
+    /// 下载器可以长期运行并随时加入新的任务，队列中的任务全部结束后回到 Idle 状态。
+    /// 已解析的资源使用 ResolvedResource::priority 作为优先级，其他资源的优先级为0
+    pub async fn enqueue(&self, resource: DownloadResource) -> Result<TaskHandle> {
+        let priority = resource_priority(&resource);
+        self.enqueue_with_priority(resource, priority).await
+    }
 
-        let task_id: u32;
-        match resource.clone() {
-            DownloadResource::Url(url) => {
-                // 根据字符串生成随机3232
-                task_id = generate_task_id(&url);
-            }
-            DownloadResource::Id(id) => {
-                // 尝试解析为u32，否则根据字符串生成随机u32
-                task_id = id.parse().unwrap_or_else(|_| generate_task_id(&id));
-            }
-            DownloadResource::Params(params) => {
-                // 根据拼接后的字符串生成随机3232
-                // task_id = generate_task_id(&params.join(""));
-                // 尝试解析第一个值为u32，否则根据拼接后的字符串生成随机u32
-                task_id = params[0]
-                    .parse()
-                    .unwrap_or_else(|_| generate_task_id(&params.join("")));
-            }
-            DownloadResource::HashMap(hashmap) => {
-                // 根据拼接后值的字符串生成随机3232
-                // task_id = generate_task_id(&hashmap.values().cloned().collect::<Vec<_>>().join(""));
-                // 尝试解析第一个id键的值为u32，否则根据拼接后的字符串生成随机u32
-                task_id = hashmap
-                    .get("id")
-                    .unwrap_or(&"".to_string())
-                    .parse()
-                    .unwrap_or_else(|_| {
-                        generate_task_id(&hashmap.values().cloned().collect::<Vec<_>>().join(""))
-                    });
+    /// 以指定的优先级将资源加入下载队列，数值越大越先开始
+    pub async fn enqueue_with_priority(
+        &self,
+        resource: DownloadResource,
+        priority: i32,
+    ) -> Result<TaskHandle> {
+        let entry = self.queue_entry(resource, priority).await;
+        let handle = self.register_handle(entry.id);
+        match self.push_entries(vec![entry]).await {
+            Ok(()) => Ok(handle),
+            Err(e) => {
+                self.close_handles(&[handle.id()]);
+                Err(e)
             }
-            DownloadResource::Resolved(resolved) => {
-                // 直接使用resolved的id
-                task_id = resolved.id;
+        }
+    }
+
+    /// 将资源加入下载队列并返回任务句柄，await 句柄得到下载结果
+    ///
+    /// 与 enqueue 不同，无法加入队列（如正在关闭）时错误通过句柄的结果返回
+    pub async fn submit(&self, resource: DownloadResource) -> TaskHandle {
+        let priority = resource_priority(&resource);
+        let entry = self.queue_entry(resource, priority).await;
+        let handle = self.register_handle(entry.id);
+        if let Err(e) = self.push_entries(vec![entry]).await {
+            if let Some(sender) = self.handles.lock().unwrap().remove(&handle.id()) {
+                sender.finish(DownloadResult::Failed {
+                    error: e.to_string(),
+                    retryable: e.is_retryable(),
+                });
             }
         }
+        handle
+    }
 
-        let resolved = self.resolver.resolve(&resource).await?;
+    /// 生成队列条目，小文件优先的策略下先通过HEAD请求获取大小
+    async fn queue_entry(&self, resource: DownloadResource, priority: i32) -> QueuedTask {
+        let size = if self.get_options().await.schedule_policy == SchedulePolicy::SmallestFirst {
+            self.probe_size(&resource).await
+        } else {
+            None
+        };
+        QueuedTask::new(self.allocate_id(), resource, priority).with_size(size)
+    }
 
-        let mut pre_request = self.client.get(resolved.url.as_str());
+    /// 分配本下载器内唯一的任务ID，与资源自带的ID无关
+    fn allocate_id(&self) -> TaskId {
+        TaskId::new(self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
 
-        if resolved.headers.len() > 0 {
-            for (key, value) in resolved.headers.iter() {
-                pre_request = pre_request.header(key, value);
-            }
+    async fn push_entries(&self, entries: Vec<QueuedTask>) -> Result<()> {
+        if self.shutdown.lock().unwrap().is_some() {
+            return Err(ErrorKind::ShuttingDown.into());
+        }
+        let state = *self.state.read().await;
+        if matches!(state, DownloaderState::Idle | DownloaderState::Stopped) {
+            // 已停止的下载器需要先 init 才能加入新任务
+            self.transition_state(DownloaderState::Running).await?;
         }
 
-        if let Some(auth) = &resolved.auth {
-            match auth {
-                AuthMethod::Basic { username, password } => {
-                    let value = format!("{}:{}", username, password);
-                    let base64 = base64_simd::STANDARD;
-                    let encoded = base64.encode_to_string(value.as_bytes());
-                    let header = format!("Basic {}", encoded);
-                    pre_request = pre_request.header("Authorization", header);
-                }
-                AuthMethod::Bearer { token } => {
-                    pre_request = pre_request.header("Authorization", format!("Bearer {}", token));
-                }
-                AuthMethod::ApiKey { key, header } => {
-                    pre_request = pre_request.header(header, key);
+        // 与排队中或下载中的任务是同一资源时不再排队，等待那次下载的结果
+        let mut queued = Vec::new();
+        let mut attached = Vec::new();
+        {
+            let mut shared = self.shared.lock().unwrap();
+            for entry in entries {
+                let Some(key) = resource_key(&entry.resource) else {
+                    queued.push(entry);
+                    continue;
+                };
+                match shared.get_mut(&key) {
+                    Some(download) => {
+                        download.waiters.push(entry.id);
+                        attached.push((entry.id, download.task_id, download.started));
+                    }
+                    None => {
+                        shared.insert(
+                            key,
+                            SharedDownload {
+                                task_id: entry.id,
+                                waiters: vec![entry.id],
+                                started: None,
+                            },
+                        );
+                        queued.push(entry);
+                    }
                 }
-                AuthMethod::None => {}
             }
         }
 
-        let pre_response = pre_request.send().await?;
-        if !pre_response.status().is_success() {
-            return Err(format!("HTTP error: {}", pre_response.status()).into());
-        }
-        let meta = DownloadMeta::from_headers(pre_response.headers());
-        drop(pre_response);
-
-        let file_path = self.generate_path(&resource, &resolved, &meta).await?;
-
-        let total_size = meta.expected_size.unwrap_or(0);
-        let mut current_len = 0;
-        if file_path.exists() {
-            let metadata = tokio::fs::metadata(&file_path).await?;
-            current_len = metadata.len();
-        }
+        self.scheduler.push(queued);
+        self.ensure_dispatcher();
 
-        if current_len == total_size {
-            self.reporter.start_task(task_id, total_size).await?;
+        for (task_id, shared_with, started) in attached {
             self.reporter
-                .finish_task(
+                .operation_result(
+                    OperationType::DownloadTask(task_id),
                     task_id,
-                    DownloadResult::Success {
-                        path: file_path.clone(),
-                        size: total_size,
-                        duration: tokio::time::Duration::from_secs(0),
-                    },
+                    200,
+                    format!(
+                        "Same resource as task {}, sharing its download",
+                        shared_with
+                    ),
                 )
-                .await?;
-            return Ok(());
-        }
-
-        let mut request = self.client.get(resolved.url.as_str());
-        if current_len > 0 {
-            request = request.header("Range", format!("bytes={}-", current_len));
-        }
-
-        if resolved.headers.len() > 0 {
-            for (key, value) in resolved.headers.iter() {
-                request = request.header(key, value);
+                .await
+                .ok();
+            if let Some((total, offset)) = started {
+                self.reporter.start_task(task_id, total, offset).await.ok();
             }
         }
-        if let Some(auth) = &resolved.auth {
-            match auth {
-                AuthMethod::Basic { username, password } => {
-                    let value = format!("{}:{}", username, password);
-                    let base64 = base64_simd::STANDARD;
-                    let encoded = base64.encode_to_string(value.as_bytes());
-                    let header = format!("Basic {}", encoded);
-                    request = request.header("Authorization", header);
-                }
-                AuthMethod::Bearer { token } => {
-                    request = request.header("Authorization", format!("Bearer {}", token));
-                }
-                AuthMethod::ApiKey { key, header } => {
-                    request = request.header(header, key);
+        Ok(())
+    }
+
+    /// 报告任务开始，共享这次下载的任务一并收到
+    async fn start_shared(&self, task_id: TaskId, total: Option<u64>, offset: u64) -> Result<()> {
+        let waiters = {
+            let mut shared = self.shared.lock().unwrap();
+            match shared.values_mut().find(|d| d.task_id == task_id) {
+                Some(download) => {
+                    download.started = Some((total, offset));
+                    download.waiters.clone()
                 }
-                AuthMethod::None => {}
+                None => vec![task_id],
             }
+        };
+        for waiter in waiters {
+            self.reporter.start_task(waiter, total, offset).await?;
         }
-        let response = request.send().await?;
+        Ok(())
+    }
 
-        if !response.status().is_success()
-            && response.status() != reqwest::StatusCode::PARTIAL_CONTENT
-        {
-            return Err(format!("HTTP error: {}", response.status()).into());
+    /// 报告任务结果，共享这次下载的任务各自收到一份
+    async fn finish_shared(&self, task_id: TaskId, result: DownloadResult) -> Result<()> {
+        let waiters = self.take_waiters(task_id).unwrap_or_else(|| vec![task_id]);
+        for waiter in waiters {
+            self.report_finish(waiter, result.clone()).await?;
         }
+        Ok(())
+    }
 
-        let task_url = resolved.url.clone();
-
-        let task = DownloadTask::new(task_id, task_url, file_path.clone(), total_size);
-
-        {
-            self.tasks.write().await.push(task.clone());
+    /// 报告任务结果，任务的句柄同时收到
+    async fn report_finish(&self, task_id: TaskId, result: DownloadResult) -> Result<()> {
+        if let Some(handle) = self.handles.lock().unwrap().remove(&task_id) {
+            handle.finish(result.clone());
         }
+        self.reporter.finish_task(task_id, result).await
+    }
 
-        self.reporter.start_task(task_id, total_size).await?;
-
-        let mut file = tokio::fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&file_path)
-            .await?;
-
-        let mut downloaded = current_len;
-        let mut stream = response.bytes_stream();
+    /// 更新共享这次下载的任务的句柄中的进度
+    fn publish_progress(&self, task_id: TaskId, progress: &DownloadProgress) {
+        if self.handles.lock().unwrap().is_empty() {
+            return;
+        }
+        let waiters = {
+            let shared = self.shared.lock().unwrap();
+            shared
+                .values()
+                .find(|d| d.task_id == task_id)
+                .map_or_else(|| vec![task_id], |d| d.waiters.clone())
+        };
+        let handles = self.handles.lock().unwrap();
+        for waiter in waiters {
+            if let Some(handle) = handles.get(&waiter) {
+                handle.progress(progress);
+            }
+        }
+    }
 
-        let start_time = tokio::time::Instant::now();
+    /// 为即将入队的任务创建句柄
+    fn register_handle(&self, task_id: TaskId) -> TaskHandle {
+        let (handle, sender) = TaskHandle::new(task_id, self.clone());
+        self.handles.lock().unwrap().insert(task_id, sender);
+        handle
+    }
 
-        while let Some(chunk) = stream.next().await {
-            let global_state = self.state.read().await;
+    /// 不会再有结果的任务（如未开始就出队），其句柄收到 Canceled
+    fn close_handles(&self, task_ids: &[TaskId]) {
+        let mut handles = self.handles.lock().unwrap();
+        for task_id in task_ids {
+            if let Some(handle) = handles.remove(task_id) {
+                handle.finish(DownloadResult::Canceled);
+            }
+        }
+    }
 
-            match *global_state {
-                DownloaderState::Idle => {
-                    self.transition_state(DownloaderState::Running).await?;
-                    continue;
-                }
-                DownloaderState::Suspended => {
-                    task.pause().await?;
-                    let mut state_rx = self.state_notifier.subscribe();
-
-                    drop(global_state);
-                    loop {
-                        tokio::select! {
-                            state_result = tokio::time::timeout(tokio::time::Duration::from_millis(1000), state_rx.recv()) => {
-                                match state_result {
-                                    Ok(Ok(new_state)) => {
-                                        if new_state != DownloaderState::Suspended {
-                                            break;
-                                        }
-                                    }
-                                    Ok(Err(_)) => { /* 通道关闭 */
-                                        self.reporter.operation_result(OperationType::Download, task_id, 500, "State channel closed".to_string()).await.ok();
-                                     }
-                                    Err(_) => { /* 超时继续检查 */
-                                    }
-                                }
-                            }
+    /// 结束一次共享的下载，返回等待结果的任务
+    fn take_waiters(&self, task_id: TaskId) -> Option<Vec<TaskId>> {
+        let mut shared = self.shared.lock().unwrap();
+        let key = shared
+            .iter()
+            .find(|(_, d)| d.task_id == task_id)
+            .map(|(key, _)| key.clone())?;
+        shared.remove(&key).map(|d| d.waiters)
+    }
 
+    /// 实际执行该任务下载的任务ID，不共享下载时为其本身
+    fn download_id(&self, task_id: TaskId) -> TaskId {
+        let shared = self.shared.lock().unwrap();
+        shared
+            .values()
+            .find(|d| d.waiters.contains(&task_id))
+            .map_or(task_id, |d| d.task_id)
+    }
 
-                            _ = async {
-                                let current_state = self.state.read().await;
-                                if *current_state != DownloaderState::Suspended {
-                                    self.state_notifier.send(*current_state).ok();
-                                }
-                            } => {}
-                        }
+    /// 共享下载的任务不是最后一个等待者时只退出共享，不影响下载本身
+    ///
+    /// 返回 None 表示需要处理下载本身，否则返回下载是否已开始
+    fn detach_shared(&self, task_id: TaskId) -> Option<bool> {
+        let mut shared = self.shared.lock().unwrap();
+        let download = shared
+            .values_mut()
+            .find(|d| d.waiters.len() > 1 && d.waiters.contains(&task_id))?;
+        download.waiters.retain(|waiter| *waiter != task_id);
+        Some(download.started.is_some())
+    }
 
-                        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    /// 通过HEAD请求获取资源大小，失败时视为未知
+    async fn probe_size(&self, resource: &DownloadResource) -> Option<u64> {
+        let resolved = self.resolver.resolve(resource).await.ok()?;
+        let _host = self.host_limiter.acquire(&resolved.url).await;
+        let response = self
+            .request_with(reqwest::Method::HEAD, &resolved)
+            .send()
+            .await
+            .ok()?;
+        let response = error_for_status(response).ok()?;
+        DownloadMeta::from_headers(response.headers()).expected_size
+    }
 
-                        let state = self.state.read().await;
-                        if *state != DownloaderState::Suspended {
-                            break;
-                        }
+    /// 移除任务：排队中的任务直接出队，已开始的任务会被取消并从任务列表中删除
+    pub async fn remove(&self, task_id: TaskId) -> Result<()> {
+        let result = if let Some(started) = self.detach_shared(task_id) {
+            if started {
+                self.report_finish(task_id, DownloadResult::Canceled)
+                    .await
+                    .ok();
+            } else {
+                self.close_handles(&[task_id]);
+            }
+            Ok(())
+        } else {
+            // 执行下载的任务可能已被移除，由最后一个共享的任务代表这次下载
+            let download_id = self.download_id(task_id);
+            if self.unqueue(download_id).await {
+                Ok(())
+            } else {
+                match self.find_task(task_id).await {
+                    Some(task) => {
+                        let result = task.cancel().await;
+                        task.join().await;
+                        self.tasks.remove(task.id);
+                        let _guard = self.journal.lock().await;
+                        result.and(self.store.remove_task(&task.key).await)
                     }
-
-                    task.resume().await?;
+                    None => Err(ErrorKind::TaskNotFound(task_id).into()),
                 }
-                DownloaderState::Stopped => {
-                    task.cancel().await?;
-                    self.reporter
-                        .operation_result(
-                            OperationType::Download,
-                            task_id,
-                            200,
-                            "Download stopped".to_string(),
-                        )
-                        .await
-                        .ok();
-                    self.reporter
-                        .finish_task(task_id, DownloadResult::Canceled)
-                        .await?;
-                    self.save_state().await?;
-                    self.cancel_token.cancel();
-                    return Ok(());
-                }
-                DownloaderState::Running => {}
             }
+        };
+        self.report_operation(
+            OperationType::RemoveTask(task_id),
+            task_id,
+            &result,
+            "Task removed",
+        )
+        .await;
+        result
+    }
 
-            let task_state = task.state.read().await;
-
-            match *task_state {
-                TaskState::Pending => {
-                    drop(task_state);
-                    task.start().await?;
-                }
-                TaskState::Paused => {
-                    let mut state_rx = self.state_notifier.subscribe();
-                    drop(task_state);
-                    loop {
-                        tokio::select! {
-                            state_result = tokio::time::timeout(tokio::time::Duration::from_millis(1000), state_rx.recv()) => {
-                                match state_result {
-                                    Ok(Ok(new_state)) => {
-                                        if new_state != DownloaderState::Suspended {
-                                            break;
-                                        }
-                                    }
-                                    Ok(Err(_)) => { /* 通道关闭 */
-                                        self.reporter.operation_result(OperationType::Download, task_id, 500, "State channel closed".to_string()).await.ok();
-                                    }
-                                    Err(_) => { /* 超时继续检查 */ }
-                                }
-                            }
+    /// 将排队中的任务（及共享它的任务）移出队列，任务不在队列中时返回 false
+    async fn unqueue(&self, task_id: TaskId) -> bool {
+        if !self.scheduler.remove(task_id) {
+            return false;
+        }
+        let waiters = self.take_waiters(task_id).unwrap_or_else(|| vec![task_id]);
+        self.close_handles(&waiters);
+        if self.scheduler.is_idle() {
+            self.on_idle().await;
+        }
+        true
+    }
 
-                            _ = async {
-                                let current_state = self.state.read().await;
-                                if *current_state != DownloaderState::Suspended {
-                                    self.state_notifier.send(*current_state).ok();
-                                }
-                            } => {}
-                        }
+    /// 调整排队中任务的优先级（数值越大越先开始），已开始的任务不受影响
+    pub async fn reprioritize(&self, task_id: TaskId, priority: i32) -> Result<()> {
+        let result = if self
+            .scheduler
+            .reprioritize(self.download_id(task_id), priority)
+        {
+            Ok(())
+        } else {
+            Err(ErrorKind::TaskNotFound(task_id).into())
+        };
+        self.report_operation(
+            OperationType::ReprioritizeTask(task_id),
+            task_id,
+            &result,
+            &format!("Priority set to {}", priority),
+        )
+        .await;
+        result
+    }
 
-                        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    /// 等待队列为空且没有进行中的任务
+    pub async fn wait_idle(&self) {
+        self.scheduler.wait_idle().await;
+    }
 
-                        let state = self.state.read().await;
-                        if *state != DownloaderState::Suspended {
-                            break;
-                        }
-                    }
-                }
-                TaskState::Canceled => {
-                    self.reporter
-                        .finish_task(task_id, DownloadResult::Canceled)
-                        .await?;
-                    self.reporter
-                        .operation_result(
-                            OperationType::Download,
-                            task_id,
-                            200,
-                            "Download canceled".to_string(),
-                        )
-                        .await
-                        .ok();
-                    drop(task_state);
-                    self.save_state().await?;
-                    return Ok(());
-                }
-                TaskState::Failed | TaskState::Completed => {
+    /// 首次入队时启动分派循环：队列中有任务时等待空闲名额，再按调度策略取出下一个任务开始下载
+    fn ensure_dispatcher(&self) {
+        let stop = {
+            let mut dispatcher = self.dispatcher.lock().unwrap();
+            if dispatcher.as_ref().is_some_and(|stop| !stop.is_cancelled()) {
+                return;
+            }
+            let stop = CancellationToken::new();
+            *dispatcher = Some(stop.clone());
+            stop
+        };
+        // 只持有弱引用，下载器的所有克隆都被释放后分派循环随之结束
+        let inner = Arc::downgrade(&self.inner);
+        let scheduler = self.scheduler.clone();
+        let dispatcher = self.dispatcher.clone();
+        tokio::spawn(async move {
+            loop {
+                let slot = tokio::select! {
+                    _ = stop.cancelled() => break,
+                    slot = async {
+                        scheduler.wait_queued().await;
+                        scheduler.acquire().await
+                    } => slot,
+                };
+                let Ok(slot) = slot else {
                     break;
-                }
-                TaskState::Downloading => {}
+                };
+                // 等待名额期间任务可能已被移出队列，此时归还名额
+                let Some(entry) = scheduler.pop() else {
+                    continue;
+                };
+                let Some(inner) = inner.upgrade() else {
+                    break;
+                };
+                let downloader = Downloader { inner };
+                let control =
+                    TaskControl::new(downloader.cancel_token.lock().unwrap().child_token());
+                downloader
+                    .running
+                    .lock()
+                    .unwrap()
+                    .insert(entry.id, control.clone());
+                let worker = downloader.clone();
+                control
+                    .spawn(async move { worker.run_queued(entry, slot).await })
+                    .await;
             }
-
-            let chunk = chunk?;
-            downloaded += chunk.len() as u64;
-
-            let progress = self.calculate_progress(downloaded, total_size, start_time);
-
-            {
-                *task.progress.lock().await = progress.clone();
+            // 之后入队的任务会启动新的分派循环；已被新的循环取代时保留它的令牌
+            stop.cancel();
+            let mut dispatcher = dispatcher.lock().unwrap();
+            if dispatcher.as_ref().is_some_and(|stop| stop.is_cancelled()) {
+                *dispatcher = None;
             }
+        });
+    }
 
-            self.reporter.update_progress(task_id, &progress).await?;
-
-            if last_save.elapsed() >= save_interval {
-                self.save_state().await?;
-                last_save = tokio::time::Instant::now();
+    async fn run_queued(&self, entry: QueuedTask, slot: Slot) {
+        let result = self.download_task(entry.id, entry.resource, slot).await;
+        self.running.lock().unwrap().remove(&entry.id);
+        // 关闭时需要根据任务状态汇总结果，此时不再移除已结束的任务
+        if self.shutdown.lock().unwrap().is_none() {
+            let retention = self.options.read().await.retention;
+            let retired = self.tasks.retire(entry.id, retention).await;
+            if let Err(e) = self.forget_records(retired).await {
+                self.reporter
+                    .operation_result(
+                        OperationType::Download,
+                        TaskId::NONE,
+                        500,
+                        format!("Failed to save state: {}", e),
+                    )
+                    .await
+                    .ok();
             }
-
-            file.write_all(&chunk).await?;
         }
-
-        file.sync_all().await?;
-        drop(file);
-
-        let final_size = tokio::fs::metadata(&file_path).await?;
-        if final_size.len() == total_size {
-            task.transition_state(TaskState::Completed).await?;
+        let canceled = result
+            .as_ref()
+            .is_err_and(|e| matches!(e.kind(), ErrorKind::Canceled));
+        if canceled {
             self.reporter
                 .operation_result(
                     OperationType::Download,
-                    task_id,
+                    entry.id,
                     200,
-                    "Download task success".to_string(),
+                    "Download canceled".to_string(),
                 )
                 .await
                 .ok();
-            self.reporter
-                .finish_task(
-                    task_id,
-                    DownloadResult::Success {
-                        path: file_path.clone(),
-                        size: total_size,
-                        duration: start_time.elapsed(),
-                    },
-                )
-                .await?;
-        } else {
-            tokio::fs::remove_file(&file_path).await?;
-            task.transition_state(TaskState::Failed).await?;
+        } else if let Err(e) = &result {
             self.reporter
                 .operation_result(
                     OperationType::Download,
-                    task_id,
+                    TaskId::NONE,
                     500,
-                    "Downloaded size mismatch".to_string(),
+                    format!("Failed to download resource: {}", e),
                 )
                 .await
                 .ok();
+        }
+        // 任务开始前就结束（如请求失败）时没有报告结果，共享这次下载的其他任务在这里收到
+        let finish = match &result {
+            Ok(()) => DownloadResult::Canceled,
+            Err(_) if canceled => DownloadResult::Canceled,
+            Err(e) => DownloadResult::Failed {
+                error: e.to_string(),
+                retryable: e.is_retryable(),
+            },
+        };
+        if let Some(waiters) = self.take_waiters(entry.id) {
+            for waiter in waiters.into_iter().filter(|waiter| *waiter != entry.id) {
+                self.report_finish(waiter, finish.clone()).await.ok();
+            }
+        }
+        // 任务本身的结果只通过 operation_result 报告过，句柄仍需收到
+        if let Some(handle) = self.handles.lock().unwrap().remove(&entry.id) {
+            handle.finish(finish);
+        }
+        if self.scheduler.finish() {
+            self.on_idle().await;
+        }
+    }
+
+    /// 队列中的任务全部结束：整理状态文件，下载器回到 Idle 状态
+    async fn on_idle(&self) {
+        if let Err(e) = self.finish_batch().await {
             self.reporter
-                .finish_task(
-                    task_id,
-                    DownloadResult::Failed {
-                        error: format!(
-                            "Downloaded size mismatch: {} != {}",
-                            final_size.len(),
-                            total_size
-                        ),
-                        retryable: true,
-                    },
+                .operation_result(
+                    OperationType::Download,
+                    TaskId::NONE,
+                    500,
+                    format!("Failed to save state: {}", e),
                 )
-                .await?;
+                .await
+                .ok();
         }
-
-        self.save_state().await?;
-
-        Ok(())
+        self.scheduler.publish_idle();
     }
 
-    pub async fn pause_task(&self, task_id: u32) -> Result<()> {
-        let tasks = self.tasks.write().await;
-        if let Some(task) = tasks.iter().find(|t| t.id == task_id) {
-            task.pause().await?;
-            Ok(())
+    async fn finish_batch(&self) -> Result<()> {
+        let state = *self.state.read().await;
+
+        if state == DownloaderState::Stopped {
+            // 停止时任务列表已被清空，状态文件由各任务自行保存
         } else {
-            Err(format!("Task {} not found", task_id).into())
+            self.persist_tasks().await?;
         }
-    }
 
-    pub async fn resume_task(&self, task_id: u32) -> Result<()> {
-        let tasks = self.tasks.write().await;
-        if let Some(task) = tasks.iter().find(|t| t.id == task_id) {
-            task.resume().await?;
-            Ok(())
-        } else {
-            Err(format!("Task {} not found", task_id).into())
+        // 暂停中的下载器保持 Suspended，等待期间又有任务入队时保持 Running
+        if state == DownloaderState::Running && self.scheduler.is_idle() {
+            self.transition_state(DownloaderState::Idle).await.ok();
         }
+
+        self.reporter
+            .operation_result(
+                OperationType::Download,
+                TaskId::NONE,
+                200,
+                "All Tasks Completed".to_string(),
+            )
+            .await?;
+
+        Ok(())
     }
 
-    pub async fn cancel_task(&self, task_id: u32) -> Result<()> {
-        let tasks = self.tasks.write().await;
-        if let Some(task) = tasks.iter().find(|t| t.id == task_id) {
-            task.cancel().await?;
-            Ok(())
+    /// 仍有未完成的任务时保存状态以便之后续传，否则清空存储
+    async fn persist_tasks(&self) -> Result<()> {
+        let mut all_completed = true;
+        for task in self.tasks.snapshot() {
+            all_completed &= *task.state.read().await == TaskState::Completed;
+        }
+        if all_completed {
+            // 所有任务都被跳过时不会生成状态文件
+            let _guard = self.journal.lock().await;
+            self.store.clear().await
         } else {
-            Err(format!("Task {} not found", task_id).into())
+            self.save_state().await
         }
     }
 
-    pub async fn save_state(&self) -> Result<()> {
-        let tasks = self.tasks.write().await;
-        let mut task_states = Vec::new();
-        for task in tasks.iter() {
-            let progress = task.progress.lock().await;
-            let task_state = TaskStateRecord {
-                id: task.id,
-                url: task.url.clone(),
-                downloaded_bytes: progress.bytes_downloaded,
-                total_bytes: progress.total_bytes,
-                file_path: task.file_path.clone(),
-                state: task.state.read().await.clone(),
-            };
-            task_states.push(task_state);
+    async fn download_task(
+        &self,
+        task_id: TaskId,
+        resource: DownloadResource,
+        mut slot: Slot,
+    ) -> Result<()> {
+        let global_state = self.state.read().await;
+        if *global_state == DownloaderState::Stopped {
+            return Ok(());
         }
-        let state = PersistentState { tasks: task_states };
+        drop(global_state);
+
+        // 直接调用（不经过分派循环）时没有登记，使用新的子令牌
+        let control = self
+            .running
+            .lock()
+            .unwrap()
+            .get(&task_id)
+            .cloned()
+            .unwrap_or_else(|| TaskControl::new(self.cancel_token.lock().unwrap().child_token()));
+        let cancel_token = control.cancel_token.clone();
+
+        let resolved = cancellable(&cancel_token, self.resolver.resolve(&resource)).await?;
+
+        let mut meta = self
+            .with_retry(task_id, &cancel_token, || {
+                cancellable(&cancel_token, async {
+                    let _host = self.host_limiter.acquire(&resolved.url).await;
+                    let pre_response = error_for_status(self.request(&resolved).send().await?)?;
+                    Ok(DownloadMeta::from_headers(pre_response.headers()))
+                })
+            })
+            .await?;
+        meta.checksum = resolved.checksum.clone();
 
-        let contents = serde_json::to_string(&state)?;
+        let file_path = self.generate_path(&resource, &resolved, &meta).await?;
 
+        let total_size = meta.expected_size;
         let options = self.get_options().await;
 
-        let downloading_path = PathBuf::from(&options.save_path).join("downloading.json");
+        // 覆盖策略下，已存在的完整文件视为已下载，校验失败时重新下载
+        if options.path_policy.conflict == "overwrite"
+            && total_size.is_some()
+            && self.is_downloaded(task_id, &file_path, &meta).await?
+        {
+            // 上次会话留下的未完成记录已经没有意义
+            self.tasks.remove_if(|t| t.file_path == file_path);
+            self.start_shared(task_id, total_size, total_size.unwrap_or_default())
+                .await?;
+            self.finish_shared(
+                task_id,
+                DownloadResult::Success {
+                    path: file_path.clone(),
+                    size: total_size.unwrap_or_default(),
+                    duration: tokio::time::Duration::from_secs(0),
+                },
+            )
+            .await?;
+            return Ok(());
+        }
 
-        tokio::fs::write(downloading_path, contents).await?;
+        // 数据先写入临时文件，完成并校验后再重命名为目标文件
+        let part = PartFile::new(&file_path);
+        let mut current_len = part.resume_len().await?;
+        let resumed = self
+            .resumed
+            .lock()
+            .unwrap()
+            .remove(&content_key(&resolved.url));
 
-        Ok(())
-    }
+        // 已有临时文件无法安全续传时从头下载
+        if current_len > 0 {
+            let previous = match part.load_meta().await {
+                Some(part_meta) => Some((part_meta.etag, part_meta.last_modified)),
+                None => self.previous_validator(&file_path).await,
+            };
+            let changed = previous.is_some_and(|(etag, last_modified)| {
+                !meta.matches_validator(etag.as_deref(), last_modified.as_deref())
+            });
+            let partial = total_size != Some(current_len);
+
+            let reason = if changed {
+                Some("Remote file changed since the last download")
+            } else if total_size.is_none() {
+                Some("Remote file size is unknown")
+            } else if partial && !options.resume_download && !resumed {
+                Some("Resume is disabled")
+            } else {
+                None
+            };
 
-    pub async fn load_state(&self, state: PersistentState) -> Result<()> {
-        let mut tasks = self.tasks.write().await;
-        for task_state in state.tasks {
-            let task = DownloadTask::new(
-                task_state.id,
-                task_state.url,
-                task_state.file_path,
-                task_state.total_bytes,
-            );
+            if let Some(reason) = reason {
+                self.reporter
+                    .operation_result(
+                        OperationType::DownloadTask(task_id),
+                        task_id,
+                        200,
+                        format!("{}, restarting from the beginning", reason),
+                    )
+                    .await
+                    .ok();
+                part.discard().await?;
+                current_len = 0;
+            }
+        }
 
-            let start_time = tokio::time::Instant::now();
-            let progress = self.calculate_progress(
-                task_state.downloaded_bytes,
-                task_state.total_bytes,
-                start_time,
-            );
+        if options.create_dirs {
+            if let Some(parent) = file_path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+        }
+        let part_meta = PartMeta {
+            url: resolved.url.clone(),
+            total_size,
+            etag: meta.etag.clone(),
+            last_modified: meta.last_modified.clone(),
+            valid_len: None,
+        };
+        part.save_meta(&part_meta).await?;
 
-            *task.progress.lock().await = progress;
+        let task_url = resolved.url.clone();
 
-            *task.state.write().await = task_state.state;
+        let task = DownloadTask::new(task_id, task_url, file_path.clone(), total_size)
+            .with_validator(meta.etag.clone(), meta.last_modified.clone())
+            .with_resolved(resolved.clone())
+            .with_control(control);
 
-            tasks.push(task);
-        }
-        Ok(())
-    }
+        // 同一文件的旧记录（如上次会话载入的）由本次任务取代
+        self.tasks.remove_if(|t| t.file_path == file_path);
+        self.tasks.insert(task.clone());
 
-    pub async fn load_state_from_file(&mut self, state_path: String) -> Result<()> {
-        if let Ok(contents) = tokio::fs::read_to_string(state_path).await {
-            let state: PersistentState = serde_json::from_str(&contents)?;
+        self.start_shared(task_id, total_size, current_len).await?;
 
-            let mut tasks = self.tasks.write().await;
-            for task_state in state.tasks {
-                let task = DownloadTask::new(
-                    task_state.id,
-                    task_state.url,
-                    task_state.file_path,
-                    task_state.total_bytes,
-                );
+        // 续传时先补算磁盘上已有部分的摘要
+        let verifier = match &meta.checksum {
+            Some(checksum) => {
+                let mut verifier = ChecksumVerifier::new(checksum.clone())?;
+                verifier.catch_up(part.path(), current_len).await?;
+                Some(std::sync::Mutex::new(verifier))
+            }
+            None => None,
+        };
 
-                let start_time = tokio::time::Instant::now();
-                let progress = self.calculate_progress(
-                    task_state.downloaded_bytes,
-                    task_state.total_bytes,
-                    start_time,
-                );
+        let mut transfer = Transfer {
+            task: task.clone(),
+            total_size,
+            initial_size: current_len,
+            if_range: meta.if_range(),
+            // 服务器不支持范围请求时断开连接就得从头下载，只能保持连接
+            release_on_pause: !options.keep_connection_on_pause && meta.accept_ranges,
+            remote_changed: std::sync::Mutex::new(None),
+            part: part.clone(),
+            part_meta,
+            downloaded: AtomicU64::new(current_len),
+            start_time: tokio::time::Instant::now(),
+            rate_sample: std::sync::Mutex::new(RateSample {
+                at: tokio::time::Instant::now(),
+                bytes: current_len,
+                rate: None,
+            }),
+            verifier,
+        };
 
-                *task.progress.lock().await = progress;
+        let mut restarted = false;
+        let flow = loop {
+            let current_len = transfer.downloaded.load(Ordering::SeqCst);
+
+            // 服务器支持范围请求时，将剩余部分切分为多个分段并行下载
+            let segments = match total_size {
+                Some(total_size)
+                    if options.enable_range && meta.accept_ranges && total_size > 0 =>
+                {
+                    split_segments(
+                        current_len,
+                        total_size,
+                        options.chunk_size,
+                        options.connections_per_task,
+                    )
+                }
+                _ => Vec::new(),
+            };
 
-                *task.state.write().await = task_state.state;
+            let flow = if total_size == Some(current_len) {
+                // 上次已下载完成但尚未重命名
+                Ok(TaskFlow::Continue)
+            } else if segments.len() > 1 {
+                self.download_segmented(
+                    &transfer,
+                    &resolved,
+                    part.path(),
+                    &segments,
+                    options.connections_per_task as usize,
+                )
+                .await
+            } else {
+                self.with_retry(task_id, &cancel_token, || {
+                    self.download_single(&transfer, &resolved, part.path())
+                })
+                .await
+            };
 
-                tasks.push(task);
+            // 分段下载中远程文件已变化，其他分段已随之放弃，从头重新下载一次
+            if let Err(e) = &flow
+                && matches!(e.kind(), ErrorKind::RemoteChanged { .. })
+                && !restarted
+            {
+                let changed = transfer.remote_changed.lock().unwrap().take();
+                if let Some(changed) = changed {
+                    self.restart_transfer(&mut transfer, changed).await?;
+                    restarted = true;
+                    continue;
+                }
             }
-        }
-        Ok(())
-    }
-    fn calculate_progress(
-        &self,
-        downloaded: u64,
-        total: u64,
-        start_time: tokio::time::Instant,
-    ) -> DownloadProgress {
-        let elapsed = start_time.elapsed();
-        let (rate, remaining_time, progress) = rate_remaining_progress(downloaded, total, elapsed);
-
-        DownloadProgress {
-            bytes_downloaded: downloaded,
-            total_bytes: total,
-            rate,
-            remaining_time,
-            progress_percentage: progress * 100.0,
-        }
-    }
-}
 
-// Convenience function for multi-download
-pub async fn download_urls(
-    resources: Vec<DownloadResource>,
-    options: DownloadOptions,
-    resolver: Box<dyn ResourceResolver>,
-    reporter: Box<dyn CombinedReporter>,
-) -> Result<()> {
-    let downloader = Downloader::new(options, resolver, reporter);
-    downloader.download_multi(resources).await
-}
+            if !matches!(flow, Ok(TaskFlow::Paused)) {
+                break flow;
+            }
 
-// Quick multi-download with default settings
-pub async fn quick_download_multi(
+            // 连接已断开，让出并发名额直到任务恢复，之后重新排队并从断点发起范围请求
+            drop(slot);
+            self.reporter
+                .operation_result(
+                    OperationType::PauseTask(task_id),
+                    task_id,
+                    200,
+                    format!(
+                        "Connection released at {} bytes",
+                        transfer.downloaded.load(Ordering::SeqCst)
+                    ),
+                )
+                .await
+                .ok();
+            self.schedule_save();
+
+            match self.wait_while_paused(&task).await {
+                TaskFlow::Continue => {}
+                flow => break Ok(flow),
+            }
+            slot = match cancellable(&cancel_token, self.scheduler.acquire()).await {
+                Ok(slot) => slot,
+                Err(e) if matches!(e.kind(), ErrorKind::Canceled) => {
+                    break Ok(self.cancelled_flow().await);
+                }
+                Err(e) => return Err(e),
+            };
+        };
+
+        let flow = match flow {
+            Ok(flow) => flow,
+            // 取消令牌触发时进行中的操作已被放弃
+            Err(e) if matches!(e.kind(), ErrorKind::Canceled) => self.cancelled_flow().await,
+            Err(e) => {
+                task.transition_state(TaskState::Failed).await?;
+                self.finish_shared(
+                    task_id,
+                    DownloadResult::Failed {
+                        error: e.to_string(),
+                        retryable: e.is_retryable(),
+                    },
+                )
+                .await?;
+                self.schedule_save();
+                return Err(e);
+            }
+        };
+
+        // 关闭期间被单独取消的任务仍按取消处理
+        let flow = match flow {
+            TaskFlow::Stopped if *task.state.read().await == TaskState::Canceled => {
+                TaskFlow::Canceled
+            }
+            flow => flow,
+        };
+
+        match flow {
+            TaskFlow::Stopped => {
+                let checkpoint = self
+                    .shutdown
+                    .lock()
+                    .unwrap()
+                    .is_some_and(|mode| mode != ShutdownMode::Abort);
+                let message = if checkpoint {
+                    // 保留临时文件并记录为暂停，进度以磁盘上的有效长度为准
+                    task.pause().await.ok();
+                    let offset = part.resume_len().await?;
+                    task.progress.lock().await.bytes_downloaded = offset;
+                    format!("Download checkpointed at {} bytes", offset)
+                } else {
+                    task.cancel().await?;
+                    "Download stopped".to_string()
+                };
+                self.reporter
+                    .operation_result(OperationType::Download, task_id, 200, message)
+                    .await
+                    .ok();
+                self.finish_shared(task_id, DownloadResult::Canceled)
+                    .await?;
+                self.save_state().await?;
+                return Ok(());
+            }
+            TaskFlow::Canceled => {
+                self.finish_shared(task_id, DownloadResult::Canceled)
+                    .await?;
+                self.reporter
+                    .operation_result(
+                        OperationType::Download,
+                        task_id,
+                        200,
+                        "Download canceled".to_string(),
+                    )
+                    .await
+                    .ok();
+                self.schedule_save();
+                return Ok(());
+            }
+            TaskFlow::Continue | TaskFlow::Break | TaskFlow::Paused => {}
+        }
+
+        // 总大小未知时，以数据流正常结束作为完成的依据
+        let final_size = transfer.downloaded.load(Ordering::SeqCst);
+        let expected_size = total_size.unwrap_or(final_size);
+
+        if final_size == expected_size {
+            if let Some(verifier) = transfer.verifier.take() {
+                let mut verifier = verifier.into_inner().unwrap();
+                verifier.catch_up(part.path(), final_size).await?;
+                if let Err(e) = verifier.verify() {
+                    part.discard().await?;
+                    task.transition_state(TaskState::Failed).await?;
+                    self.reporter
+                        .operation_result(
+                            OperationType::Download,
+                            task_id,
+                            500,
+                            "Checksum verification failed".to_string(),
+                        )
+                        .await
+                        .ok();
+                    self.finish_shared(
+                        task_id,
+                        DownloadResult::Failed {
+                            error: e.to_string(),
+                            retryable: e.is_retryable(),
+                        },
+                    )
+                    .await?;
+                    self.schedule_save();
+                    return Err(e);
+                }
+            }
+        }
+
+        let committed = if final_size == expected_size {
+            Some(part.commit(&options.path_policy.conflict).await)
+        } else {
+            None
+        };
+
+        if let Some(Err(e)) = committed {
+            task.transition_state(TaskState::Failed).await?;
+            self.finish_shared(
+                task_id,
+                DownloadResult::Failed {
+                    error: e.to_string(),
+                    retryable: e.is_retryable(),
+                },
+            )
+            .await?;
+            self.schedule_save();
+            return Err(e);
+        }
+
+        if let Some(Ok(final_path)) = committed {
+            task.transition_state(TaskState::Completed).await?;
+            self.reporter
+                .operation_result(
+                    OperationType::Download,
+                    task_id,
+                    200,
+                    "Download task success".to_string(),
+                )
+                .await
+                .ok();
+            self.finish_shared(
+                task_id,
+                DownloadResult::Success {
+                    path: final_path,
+                    size: final_size,
+                    duration: transfer.start_time.elapsed(),
+                },
+            )
+            .await?;
+        } else {
+            part.discard().await?;
+            task.transition_state(TaskState::Failed).await?;
+            self.reporter
+                .operation_result(
+                    OperationType::Download,
+                    task_id,
+                    500,
+                    "Downloaded size mismatch".to_string(),
+                )
+                .await
+                .ok();
+            let e: Error = ErrorKind::SizeMismatch {
+                expected: expected_size,
+                actual: final_size,
+            }
+            .into();
+            self.finish_shared(
+                task_id,
+                DownloadResult::Failed {
+                    error: e.to_string(),
+                    retryable: e.is_retryable(),
+                },
+            )
+            .await?;
+            self.schedule_save();
+            return Err(e);
+        }
+
+        self.schedule_save();
+
+        Ok(())
+    }
+
+    /// 目标文件是否已经下载完成：大小一致、远程文件未变化且通过校验
+    ///
+    /// 校验失败的文件会被删除
+    async fn is_downloaded(
+        &self,
+        task_id: TaskId,
+        file_path: &PathBuf,
+        meta: &DownloadMeta,
+    ) -> Result<bool> {
+        let len = match tokio::fs::metadata(file_path).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => return Ok(false),
+        };
+        if meta.expected_size != Some(len) {
+            return Ok(false);
+        }
+        if let Some((etag, last_modified)) = self.previous_validator(file_path).await {
+            if !meta.matches_validator(etag.as_deref(), last_modified.as_deref()) {
+                return Ok(false);
+            }
+        }
+        if let Some(checksum) = &meta.checksum {
+            if let Err(e) = verify_file(file_path, checksum).await {
+                if !matches!(e.kind(), ErrorKind::ChecksumMismatch { .. }) {
+                    return Err(e);
+                }
+                self.reporter
+                    .operation_result(
+                        OperationType::DownloadTask(task_id),
+                        task_id,
+                        500,
+                        format!("Existing file is corrupt, downloading again: {}", e),
+                    )
+                    .await
+                    .ok();
+                tokio::fs::remove_file(file_path).await?;
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// 之前记录的同一文件的ETag/Last-Modified（来自已加载的状态或本次会话）
+    async fn previous_validator(
+        &self,
+        file_path: &PathBuf,
+    ) -> Option<(Option<String>, Option<String>)> {
+        self.tasks
+            .snapshot()
+            .into_iter()
+            .rev()
+            .find(|task| &task.file_path == file_path)
+            .map(|task| (task.etag.clone(), task.last_modified.clone()))
+    }
+
+    /// 构造带有资源自定义头与认证信息的GET请求
+    fn request(&self, resolved: &ResolvedResource) -> reqwest::RequestBuilder {
+        self.request_with(reqwest::Method::GET, resolved)
+    }
+
+    fn request_with(
+        &self,
+        method: reqwest::Method,
+        resolved: &ResolvedResource,
+    ) -> reqwest::RequestBuilder {
+        let client = self.client.read().unwrap().clone();
+        let mut request = client.request(method, resolved.url.as_str());
+
+        for (key, value) in resolved.headers.iter() {
+            request = request.header(key, value);
+        }
+
+        if let Some(auth) = &resolved.auth {
+            match auth {
+                AuthMethod::Basic { username, password } => {
+                    let value = format!("{}:{}", username, password);
+                    let base64 = base64_simd::STANDARD;
+                    let encoded = base64.encode_to_string(value.as_bytes());
+                    let header = format!("Basic {}", encoded);
+                    request = request.header("Authorization", header);
+                }
+                AuthMethod::Bearer { token } => {
+                    request = request.header("Authorization", format!("Bearer {}", token));
+                }
+                AuthMethod::ApiKey { key, header } => {
+                    request = request.header(header, key);
+                }
+                AuthMethod::None => {}
+            }
+        }
+        request
+    }
+
+    /// 远程文件已变化：丢弃临时文件与元数据，之后的范围请求使用变化后的校验值
+    async fn restart_transfer(&self, transfer: &mut Transfer, changed: DownloadMeta) -> Result<()> {
+        let task_id = transfer.task.id;
+        self.reporter
+            .operation_result(
+                OperationType::DownloadTask(task_id),
+                task_id,
+                200,
+                "Remote file changed during a segmented download, restarting from the beginning"
+                    .to_string(),
+            )
+            .await
+            .ok();
+        transfer.part.discard().await?;
+        transfer.if_range = changed.if_range();
+        transfer.part_meta.etag = changed.etag;
+        transfer.part_meta.last_modified = changed.last_modified;
+        transfer.part.save_meta(&transfer.part_meta).await?;
+        transfer.downloaded.store(0, Ordering::SeqCst);
+        transfer.initial_size = 0;
+        *transfer.rate_sample.lock().unwrap() = RateSample {
+            at: tokio::time::Instant::now(),
+            bytes: 0,
+            rate: None,
+        };
+        if let Some(verifier) = &transfer.verifier {
+            verifier.lock().unwrap().reset()?;
+        }
+        Ok(())
+    }
+
+    /// 单连接下载：从已写入的位置续传并追加写入
+    async fn download_single(
+        &self,
+        transfer: &Transfer,
+        resolved: &ResolvedResource,
+        file_path: &PathBuf,
+    ) -> Result<TaskFlow> {
+        let cancel_token = transfer.task.cancel_token();
+        let _host = cancellable(cancel_token, async {
+            Ok(self.host_limiter.acquire(&resolved.url).await)
+        })
+        .await?;
+        let current_len = transfer.downloaded.load(Ordering::SeqCst);
+        let mut request = self.request(resolved);
+        if current_len > 0 {
+            request = request.header("Range", format!("bytes={}-", current_len));
+            if let Some(if_range) = &transfer.if_range {
+                request = request.header("If-Range", if_range);
+            }
+        }
+        let response =
+            error_for_status(cancellable(cancel_token, request.send().err_into()).await?)?;
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file_path)
+            .await?;
+
+        // 远程文件已变化（If-Range不匹配）或服务器忽略了Range头，返回了完整内容，只能从头开始
+        if current_len > 0 && response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+            self.reporter
+                .operation_result(
+                    OperationType::DownloadTask(transfer.task.id),
+                    transfer.task.id,
+                    200,
+                    format!(
+                        "Server returned {} for a range request, restarting from the beginning",
+                        response.status()
+                    ),
+                )
+                .await
+                .ok();
+            file.set_len(0).await?;
+            transfer.downloaded.store(0, Ordering::SeqCst);
+            if let Some(verifier) = &transfer.verifier {
+                verifier.lock().unwrap().reset()?;
+            }
+        }
+
+        let flow = self.pump(transfer, response, &mut file, None).await?;
+
+        file.sync_all().await?;
+
+        // 连接提前结束，交给重试逻辑从断点继续
+        let downloaded = transfer.downloaded.load(Ordering::SeqCst);
+        if let Some(total_size) = transfer.total_size
+            && matches!(flow, TaskFlow::Continue)
+            && downloaded < total_size
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("Stream ended at {} of {} bytes", downloaded, total_size),
+            )
+            .into());
+        }
+        Ok(flow)
+    }
+
+    /// 多连接分段下载：预分配文件，每个分段使用独立连接写入各自的偏移
+    async fn download_segmented(
+        &self,
+        transfer: &Transfer,
+        resolved: &ResolvedResource,
+        file_path: &PathBuf,
+        segments: &[Segment],
+        connections: usize,
+    ) -> Result<TaskFlow> {
+        let start = segments.first().map(|s| s.start).unwrap_or_default();
+        let end = segments.last().map(|s| s.end + 1).unwrap_or_default();
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(file_path)
+            .await?;
+        // 分段写入期间文件长度不可信，中断后只能从起点续传
+        transfer
+            .part
+            .save_meta(&PartMeta {
+                valid_len: Some(start),
+                ..transfer.part_meta.clone()
+            })
+            .await?;
+        file.set_len(end).await?;
+
+        let written: Vec<AtomicU64> = segments.iter().map(|_| AtomicU64::new(0)).collect();
+        transfer.task.set_segments(segments);
+
+        let fetches: Vec<_> = segments
+            .iter()
+            .zip(written.iter())
+            .map(|(segment, written)| {
+                self.download_segment(transfer, resolved, file_path, segment, written)
+            })
+            .collect();
+
+        let result = futures::stream::iter(fetches)
+            .buffer_unordered(connections.max(1))
+            .try_collect::<Vec<TaskFlow>>()
+            .await;
+
+        // 未全部完成时，截断到连续写入的部分，保证后续可以按文件长度续传
+        let written: Vec<u64> = written.iter().map(|w| w.load(Ordering::SeqCst)).collect();
+        let prefix = contiguous_prefix(start, segments, &written);
+        if transfer.remote_changed.lock().unwrap().is_some() {
+            // 已写入的数据来自旧版本文件，全部丢弃
+            file.set_len(0).await?;
+            transfer.downloaded.store(0, Ordering::SeqCst);
+        } else if prefix < end {
+            file.set_len(prefix).await?;
+            // 暂停后恢复时从截断处继续
+            transfer.downloaded.store(prefix, Ordering::SeqCst);
+        }
+        file.sync_all().await?;
+        transfer.part.save_meta(&transfer.part_meta).await?;
+        // 文件已截断为连续的部分，分段进度不再有意义
+        transfer.task.set_segments(&[]);
+
+        let flows = result?;
+        Ok(flows
+            .into_iter()
+            .find(|flow| !matches!(flow, TaskFlow::Continue))
+            .unwrap_or(TaskFlow::Continue))
+    }
+
+    async fn download_segment(
+        &self,
+        transfer: &Transfer,
+        resolved: &ResolvedResource,
+        file_path: &PathBuf,
+        segment: &Segment,
+        written: &AtomicU64,
+    ) -> Result<TaskFlow> {
+        let result = self
+            .with_retry(
+                transfer.task.id,
+                transfer.task.cancel_token(),
+                || async move {
+                    // 任务已暂停时不再为尚未开始的分段建立连接
+                    if transfer.release_on_pause
+                        && *transfer.task.state.read().await == TaskState::Paused
+                    {
+                        return Ok(TaskFlow::Paused);
+                    }
+
+                    let cancel_token = transfer.task.cancel_token();
+                    let _host = cancellable(cancel_token, async {
+                        Ok(self.host_limiter.acquire(&resolved.url).await)
+                    })
+                    .await?;
+
+                    // 重试时从该分段已写入的位置继续
+                    let remaining = Segment {
+                        start: segment.start + written.load(Ordering::SeqCst),
+                        end: segment.end,
+                    };
+                    let mut request = self
+                        .request(resolved)
+                        .header("Range", remaining.range_header());
+                    if let Some(if_range) = &transfer.if_range {
+                        request = request.header("If-Range", if_range);
+                    }
+                    let response = error_for_status(
+                        cancellable(cancel_token, request.send().err_into()).await?,
+                    )?;
+
+                    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+                        if transfer.if_range.is_some() {
+                            *transfer.remote_changed.lock().unwrap() =
+                                Some(DownloadMeta::from_headers(response.headers()));
+                            return Err(ErrorKind::RemoteChanged {
+                                url: resolved.url.clone(),
+                            }
+                            .into());
+                        }
+                        return Err(ErrorKind::HttpStatus {
+                            code: response.status().as_u16(),
+                            url: resolved.url.clone(),
+                            retry_after: None,
+                        }
+                        .into());
+                    }
+
+                    let mut file = tokio::fs::OpenOptions::new()
+                        .write(true)
+                        .open(file_path)
+                        .await?;
+                    file.seek(std::io::SeekFrom::Start(remaining.start)).await?;
+
+                    let flow = self
+                        .pump(transfer, response, &mut file, Some((segment, written)))
+                        .await;
+                    file.flush().await?;
+                    let flow = flow?;
+
+                    let written = written.load(Ordering::SeqCst);
+                    if matches!(flow, TaskFlow::Continue) && written < segment.len() {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::UnexpectedEof,
+                            format!(
+                                "Stream ended at {} of {} bytes for range {}-{}",
+                                written,
+                                segment.len(),
+                                segment.start,
+                                segment.end
+                            ),
+                        )
+                        .into());
+                    }
+                    Ok(flow)
+                },
+            )
+            .await;
+        // 取消不作为错误返回，否则其他分段会在写入途中被丢弃
+        match result {
+            Err(e) if matches!(e.kind(), ErrorKind::Canceled) => Ok(self.cancelled_flow().await),
+            result => result,
+        }
+    }
+
+    /// 执行一次网络操作，遇到可重试的错误时按指数退避重试，最多重试 max_retries 次
+    ///
+    /// 取消令牌触发后不再重试，退避等待也会被放弃，返回 ErrorKind::Canceled
+    async fn with_retry<T, F, Fut>(
+        &self,
+        task_id: TaskId,
+        cancel_token: &CancellationToken,
+        mut operation: F,
+    ) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let max_retries = self.get_options().await.max_retries;
+        let mut attempt = 0;
+        loop {
+            if cancel_token.is_cancelled() {
+                return Err(ErrorKind::Canceled.into());
+            }
+            match operation().await {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < max_retries && e.is_retryable() => {
+                    attempt += 1;
+                    let mut delay = backoff_delay(attempt, RETRY_BASE_DELAY, RETRY_MAX_DELAY);
+                    if let ErrorKind::HttpStatus {
+                        url,
+                        retry_after: Some(seconds),
+                        ..
+                    } = e.kind()
+                    {
+                        // 服务器要求的等待时间同样适用于该主机的其他连接
+                        let retry_after =
+                            tokio::time::Duration::from_secs(*seconds).min(MAX_RETRY_AFTER);
+                        self.host_limiter.defer(url, retry_after);
+                        delay = delay.max(retry_after);
+                    }
+                    self.reporter
+                        .operation_result(
+                            OperationType::DownloadTask(task_id),
+                            task_id,
+                            500,
+                            format!(
+                                "Attempt {} of {} failed: {}, retrying in {} ms",
+                                attempt,
+                                max_retries + 1,
+                                e,
+                                delay.as_millis()
+                            ),
+                        )
+                        .await
+                        .ok();
+                    cancellable(cancel_token, async {
+                        tokio::time::sleep(delay).await;
+                        Ok(())
+                    })
+                    .await?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// 将响应体写入文件，并将进度合并到任务的 DownloadProgress 中
+    async fn pump(
+        &self,
+        transfer: &Transfer,
+        response: reqwest::Response,
+        file: &mut tokio::fs::File,
+        segment: Option<(&Segment, &AtomicU64)>,
+    ) -> Result<TaskFlow> {
+        let task = &transfer.task;
+        let mut stream = response.bytes_stream();
+        let connection_limiter =
+            RateLimiter::new(self.get_options().await.per_connection_rate_limit);
+
+        loop {
+            // 连接停滞时也能及时响应取消，已写入的数据保持完整
+            let chunk = tokio::select! {
+                biased;
+                _ = task.cancel_token().cancelled() => return Ok(self.cancelled_flow().await),
+                chunk = stream.next() => match chunk {
+                    Some(chunk) => chunk,
+                    None => break,
+                },
+            };
+            match self.checkpoint(transfer).await? {
+                TaskFlow::Continue => {}
+                flow => return Ok(flow),
+            }
+
+            let mut chunk = chunk?;
+            if let Some((segment, written)) = segment {
+                let remaining = segment.len() - written.load(Ordering::SeqCst);
+                if chunk.len() as u64 > remaining {
+                    chunk.truncate(remaining as usize);
+                }
+            }
+
+            let len = chunk.len() as u64;
+            let limited = async {
+                connection_limiter.acquire(len).await;
+                self.rate_limiter.acquire(len).await;
+            };
+            tokio::select! {
+                biased;
+                _ = task.cancel_token().cancelled() => return Ok(self.cancelled_flow().await),
+                _ = limited => {}
+            }
+
+            let offset = match segment {
+                Some((segment, written)) => segment.start + written.load(Ordering::SeqCst),
+                None => transfer.downloaded.load(Ordering::SeqCst),
+            };
+            file.write_all(&chunk).await?;
+            if let Some(verifier) = &transfer.verifier {
+                verifier.lock().unwrap().feed(offset, &chunk);
+            }
+
+            let downloaded = transfer.downloaded.fetch_add(len, Ordering::SeqCst) + len;
+
+            let progress = transfer.progress(downloaded);
+
+            {
+                *task.progress.lock().await = progress.clone();
+            }
+
+            self.reporter.update_progress(task.id, &progress).await?;
+            self.publish_progress(task.id, &progress);
+
+            let segment_done = match segment {
+                Some((segment, written)) => {
+                    // 分段进度会被持久化并用于续传，记录前确保数据已交给操作系统
+                    file.flush().await?;
+                    let written = written.fetch_add(len, Ordering::SeqCst) + len;
+                    task.record_segment(segment, written);
+                    written >= segment.len()
+                }
+                None => false,
+            };
+
+            self.schedule_save();
+
+            if segment_done {
+                break;
+            }
+        }
+
+        Ok(TaskFlow::Continue)
+    }
+
+    /// 在处理每个数据块之前检查全局状态与任务状态
+    async fn checkpoint(&self, transfer: &Transfer) -> Result<TaskFlow> {
+        let task = &transfer.task;
+        let global_state = *self.state.read().await;
+
+        match global_state {
+            DownloaderState::Idle => {
+                // 多个分段可能同时进入这里，只需有一个成功切换
+                self.transition_state(DownloaderState::Running).await.ok();
+            }
+            DownloaderState::Suspended => {
+                task.suspend().await?;
+                tokio::select! {
+                    _ = self.wait_while_suspended() => {}
+                    _ = task.cancel_token().cancelled() => return Ok(self.cancelled_flow().await),
+                }
+                // 挂起期间被单独暂停的任务保持暂停
+                task.end_suspend().await?;
+            }
+            DownloaderState::Stopped => {
+                return Ok(TaskFlow::Stopped);
+            }
+            DownloaderState::Running => {}
+        }
+
+        let mut task_state = *task.state.read().await;
+
+        if task_state == TaskState::Paused {
+            if transfer.release_on_pause {
+                return Ok(TaskFlow::Paused);
+            }
+            // 保持连接，只阻塞当前任务，直到它恢复或被取消
+            match self.wait_while_paused(task).await {
+                TaskFlow::Continue => task_state = *task.state.read().await,
+                flow => return Ok(flow),
+            }
+        }
+
+        match task_state {
+            TaskState::Pending => {
+                task.start().await?;
+            }
+            TaskState::Canceled => {
+                return Ok(TaskFlow::Canceled);
+            }
+            TaskState::Failed | TaskState::Completed => {
+                return Ok(TaskFlow::Break);
+            }
+            TaskState::Downloading | TaskState::Paused => {}
+        }
+
+        Ok(TaskFlow::Continue)
+    }
+
+    /// 取消令牌触发后的流程：下载器已停止或正在关闭时为 Stopped，否则是任务被取消
+    async fn cancelled_flow(&self) -> TaskFlow {
+        if self.shutdown.lock().unwrap().is_some()
+            || *self.state.read().await == DownloaderState::Stopped
+        {
+            TaskFlow::Stopped
+        } else {
+            TaskFlow::Canceled
+        }
+    }
+
+    /// 等待暂停的任务恢复；任务被取消或下载器停止时同样返回
+    async fn wait_while_paused(&self, task: &DownloadTask) -> TaskFlow {
+        let mut state_rx = self.state_notifier.subscribe();
+        let flow = tokio::select! {
+            _ = task.cancel_token().cancelled() => None,
+            state = task.wait_while_paused() => Some(match state {
+                TaskState::Canceled => TaskFlow::Canceled,
+                _ => TaskFlow::Continue,
+            }),
+            _ = state_rx.wait_for(|state| *state == DownloaderState::Stopped) => Some(TaskFlow::Stopped),
+        };
+        match flow {
+            Some(flow) => flow,
+            None => self.cancelled_flow().await,
+        }
+    }
+
+    /// 等待下载器离开 Suspended 状态
+    async fn wait_while_suspended(&self) {
+        // wait_for 会先检查当前值，不会错过订阅前发生的状态变化
+        let mut state_rx = self.state_notifier.subscribe();
+        state_rx
+            .wait_for(|state| *state != DownloaderState::Suspended)
+            .await
+            .ok();
+    }
+
+    /// 暂停单个任务，只影响该任务；默认会断开连接并让出并发名额
+    pub async fn pause_task(&self, task_id: TaskId) -> Result<()> {
+        let result = match self.find_task(task_id).await {
+            Some(task) => task.pause().await,
+            None => Err(ErrorKind::TaskNotFound(task_id).into()),
+        };
+        self.report_operation(
+            OperationType::PauseTask(task_id),
+            task_id,
+            &result,
+            "Task paused",
+        )
+        .await;
+        result
+    }
+
+    /// 恢复暂停的任务，断开过连接的任务会重新排队并通过范围请求续传
+    pub async fn resume_task(&self, task_id: TaskId) -> Result<()> {
+        let result = match self.find_task(task_id).await {
+            Some(task) => task.resume().await,
+            None => Err(ErrorKind::TaskNotFound(task_id).into()),
+        };
+        self.report_operation(
+            OperationType::ResumeTask(task_id),
+            task_id,
+            &result,
+            "Task resumed",
+        )
+        .await;
+        result
+    }
+
+    /// 查找执行下载的任务，共享下载的任务会找到实际下载的那一个
+    async fn find_task(&self, task_id: TaskId) -> Option<DownloadTask> {
+        self.tasks.get(self.download_id(task_id))
+    }
+
+    async fn report_operation(
+        &self,
+        operation: OperationType,
+        task_id: TaskId,
+        result: &Result<()>,
+        message: &str,
+    ) {
+        let (code, message) = match result {
+            Ok(()) => (200, message.to_string()),
+            Err(e) => (500, e.to_string()),
+        };
+        self.reporter
+            .operation_result(operation, task_id, code, message)
+            .await
+            .ok();
+    }
+
+    /// 取消任务，等到它释放文件与连接后返回；排队中的任务直接出队
+    pub async fn cancel_task(&self, task_id: TaskId) -> Result<()> {
+        if let Some(started) = self.detach_shared(task_id) {
+            if started {
+                self.report_finish(task_id, DownloadResult::Canceled)
+                    .await?;
+            } else {
+                self.close_handles(&[task_id]);
+            }
+            return Ok(());
+        }
+        if self.unqueue(self.download_id(task_id)).await {
+            return Ok(());
+        }
+        if let Some(task) = self.find_task(task_id).await {
+            task.cancel().await?;
+            task.join().await;
+            return Ok(());
+        }
+        // 尚未创建任务（解析或预请求中）的下载只能通过令牌取消
+        let control = self
+            .running
+            .lock()
+            .unwrap()
+            .get(&self.download_id(task_id))
+            .cloned();
+        match control {
+            Some(control) => {
+                control.cancel_token.cancel();
+                control.join().await;
+                Ok(())
+            }
+            None => Err(ErrorKind::TaskNotFound(task_id).into()),
+        }
+    }
+
+    /// 删除已移出任务表的任务在存储中的记录，否则续传时它们会被重新下载
+    ///
+    /// 同一资源仍有其他任务时保留记录，由之后的保存覆盖
+    async fn forget_records(&self, tasks: Vec<DownloadTask>) -> Result<()> {
+        if tasks.is_empty() {
+            return Ok(());
+        }
+        let _guard = self.journal.lock().await;
+        let live = self.tasks.snapshot();
+        for task in tasks {
+            if !live.iter().any(|t| t.key == task.key) {
+                self.store.remove_task(&task.key).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 立即写入状态文件
+    pub async fn save_state(&self) -> Result<()> {
+        let _guard = self.journal.lock().await;
+        let state = self.snapshot_state().await;
+        self.store.upsert_all(&state.tasks).await
+    }
+
+    /// 安排一次延迟写入，SAVE_INTERVAL 内来自各任务的保存请求合并为一次
+    fn schedule_save(&self) {
+        if !self.journal.mark_dirty() {
+            return;
+        }
+        let downloader = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(SAVE_INTERVAL).await;
+            if !downloader.journal.take_scheduled() {
+                return;
+            }
+            if let Err(e) = downloader.save_state().await {
+                downloader
+                    .reporter
+                    .operation_result(
+                        OperationType::Download,
+                        TaskId::NONE,
+                        500,
+                        format!("Failed to save state: {}", e),
+                    )
+                    .await
+                    .ok();
+            }
+        });
+    }
+
+    /// 当前所有任务的记录，基于任务表的快照
+    async fn snapshot_state(&self) -> PersistentState {
+        let tasks = self.tasks.snapshot();
+        let mut records = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            let progress = task.progress.lock().await.clone();
+            let resolved = task.resolved.as_ref();
+            records.push(TaskStateRecord {
+                id: task.id,
+                url: task.url.clone(),
+                key: task.key.clone(),
+                downloaded_bytes: progress.bytes_downloaded,
+                total_bytes: progress.total_bytes,
+                file_path: task.file_path.clone(),
+                state: *task.state.read().await,
+                etag: task.etag.clone(),
+                last_modified: task.last_modified.clone(),
+                resource_id: resolved.map_or(0, |r| r.id),
+                headers: resolved.map(|r| r.headers.clone()).unwrap_or_default(),
+                auth: resolved
+                    .and_then(|r| r.auth.as_ref())
+                    .and_then(AuthRef::from_method),
+                checksum: resolved.and_then(|r| r.checksum.clone()),
+                segments: task.segments(),
+            });
+        }
+        PersistentState::new(records)
+    }
+
+    /// 载入状态中的记录（不会开始下载），续传时用其中的ETag/Last-Modified校验远程文件
+    pub async fn load_state(&self, state: PersistentState) -> Result<()> {
+        for task_state in state.tasks {
+            // 上次会话的ID可能与本次分配的冲突，重新分配
+            let task = DownloadTask::new(
+                self.allocate_id(),
+                task_state.url.clone(),
+                task_state.file_path.clone(),
+                task_state.total_bytes,
+            )
+            .with_validator(task_state.etag.clone(), task_state.last_modified.clone())
+            .with_resolved(task_state.to_resolved());
+
+            let start_time = tokio::time::Instant::now();
+            let progress = self.calculate_progress(
+                task_state.downloaded_bytes,
+                task_state.total_bytes,
+                start_time,
+            );
+
+            *task.progress.lock().await = progress;
+
+            *task.state.write().await = task_state.state;
+
+            self.tasks.insert(task);
+        }
+        Ok(())
+    }
+
+    /// 载入状态文件中的记录（不会开始下载），文件不存在时忽略
+    pub async fn load_state_from_file(&self, state_path: String) -> Result<()> {
+        match JsonStateStore::new(state_path).load().await? {
+            Some(state) => self.load_state(state).await,
+            None => Ok(()),
+        }
+    }
+
+    /// 载入状态文件并继续其中未完成且未取消的任务，返回重新排队的任务
+    ///
+    /// 已下载的部分通过范围请求续传，开始时报告的 offset 为已下载的字节数
+    pub async fn resume_session(
+        &self,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<Vec<TaskHandle>> {
+        let contents = tokio::fs::read_to_string(path).await?;
+        self.resume_state(PersistentState::from_json(&contents)?)
+            .await
+    }
+
+    /// 继续状态中未完成且未取消的任务，状态可以来自任意 StateStore
+    pub async fn resume_state(&self, state: PersistentState) -> Result<Vec<TaskHandle>> {
+        let pending: Vec<TaskStateRecord> = state
+            .tasks
+            .iter()
+            .filter(|record| !matches!(record.state, TaskState::Completed | TaskState::Canceled))
+            .cloned()
+            .collect();
+        self.load_state(state).await?;
+
+        let mut entries = Vec::with_capacity(pending.len());
+        for record in pending {
+            // 记录了认证方式时凭据不在状态中，交给解析器重新解析
+            let resource = if record.auth.is_some() {
+                DownloadResource::Url(record.url.clone())
+            } else {
+                DownloadResource::Resolved(record.to_resolved())
+            };
+            let entry = self.queue_entry(resource, 0).await;
+            self.resumed.lock().unwrap().insert(record.content_key());
+
+            let part = PartFile::new(&record.file_path);
+            if record.downloaded_bytes > 0
+                && !tokio::fs::try_exists(part.path()).await.unwrap_or(false)
+            {
+                self.reporter
+                    .operation_result(
+                        OperationType::DownloadTask(entry.id),
+                        entry.id,
+                        200,
+                        format!(
+                            "Partial file of {} is missing, restarted from the beginning",
+                            record.file_path.display()
+                        ),
+                    )
+                    .await
+                    .ok();
+            } else if record.segments.is_empty() && record.downloaded_bytes > 0 {
+                // 临时文件比记录的短（如被截断）时从实际长度续传
+                let len = part.resume_len().await?;
+                if len < record.downloaded_bytes {
+                    self.reporter
+                        .operation_result(
+                            OperationType::DownloadTask(entry.id),
+                            entry.id,
+                            200,
+                            format!(
+                                "Partial file of {} has {} of {} recorded bytes, resuming from {}",
+                                record.file_path.display(),
+                                len,
+                                record.downloaded_bytes,
+                                len
+                            ),
+                        )
+                        .await
+                        .ok();
+                }
+            } else if !record.segments.is_empty() {
+                // 分段写入时临时文件只记录了分段开始前的有效长度，按记录的分段进度推进
+                if let Some(mut meta) = part.load_meta().await {
+                    let segments: Vec<Segment> = record
+                        .segments
+                        .iter()
+                        .map(|s| Segment {
+                            start: s.start,
+                            end: s.end,
+                        })
+                        .collect();
+                    let written: Vec<u64> = record.segments.iter().map(|s| s.written).collect();
+                    let prefix = contiguous_prefix(record.segments[0].start, &segments, &written);
+                    if meta.valid_len.is_some_and(|valid| valid < prefix) {
+                        meta.valid_len = Some(prefix);
+                        part.save_meta(&meta).await?;
+                    }
+                }
+            }
+            entries.push(entry);
+        }
+
+        let handles: Vec<TaskHandle> = entries
+            .iter()
+            .map(|entry| self.register_handle(entry.id))
+            .collect();
+        if !entries.is_empty() {
+            let options = self.get_options().await;
+            let mut result = Ok(());
+            if options.create_dirs {
+                result = tokio::fs::create_dir_all(&options.save_path)
+                    .await
+                    .map_err(Into::into);
+            }
+            if result.is_ok() {
+                result = self.push_entries(entries).await;
+            }
+            if let Err(e) = result {
+                let ids: Vec<TaskId> = handles.iter().map(|handle| handle.id()).collect();
+                self.close_handles(&ids);
+                return Err(e);
+            }
+        }
+        Ok(handles)
+    }
+
+    fn calculate_progress(
+        &self,
+        downloaded: u64,
+        total: Option<u64>,
+        start_time: tokio::time::Instant,
+    ) -> DownloadProgress {
+        let elapsed = start_time.elapsed();
+        let (rate, remaining_time, progress) = rate_remaining_progress(downloaded, total, elapsed);
+
+        DownloadProgress {
+            bytes_downloaded: downloaded,
+            total_bytes: total,
+            current_rate: rate,
+            average_rate: rate,
+            elapsed_time: elapsed,
+            remaining_time,
+            progress_percentage: progress.map(|p| p * 100.0),
+        }
+    }
+}
+
+/// 资源的内容键，ID、参数等资源在解析前无法判断是否相同，返回None
+fn resource_key(resource: &DownloadResource) -> Option<String> {
+    match resource {
+        DownloadResource::Url(url) => Some(content_key(url)),
+        DownloadResource::Resolved(resolved) => Some(content_key(&resolved.url)),
+        _ => None,
+    }
+}
+
+/// 已解析资源自带的优先级，其他资源为0
+fn resource_priority(resource: &DownloadResource) -> i32 {
+    match resource {
+        DownloadResource::Resolved(resolved) => resolved.priority,
+        _ => 0,
+    }
+}
+
+/// 取消令牌触发时放弃等待中的操作，返回 ErrorKind::Canceled
+async fn cancellable<T>(
+    cancel_token: &CancellationToken,
+    operation: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    tokio::select! {
+        biased;
+        _ = cancel_token.cancelled() => Err(ErrorKind::Canceled.into()),
+        result = operation => result,
+    }
+}
+
+/// 将非成功状态码转换为 ErrorKind::HttpStatus
+fn error_for_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_client_error() || status.is_server_error() {
+        let retry_after = match status {
+            reqwest::StatusCode::TOO_MANY_REQUESTS | reqwest::StatusCode::SERVICE_UNAVAILABLE => {
+                response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(parse_retry_after)
+            }
+            _ => None,
+        };
+        return Err(ErrorKind::HttpStatus {
+            code: status.as_u16(),
+            url: response.url().to_string(),
+            retry_after,
+        }
+        .into());
+    }
+    Ok(response)
+}
+
+/// 根据 DownloadOptions 构建HTTP客户端（代理、超时、重定向、TLS、UA、默认请求头）
+fn build_client(options: &DownloadOptions) -> Result<reqwest::Client> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    for (key, value) in options.headers.iter() {
+        let name = reqwest::header::HeaderName::from_bytes(key.as_bytes())
+            .map_err(|e| format!("Invalid header name {}: {}", key, e))?;
+        let value = reqwest::header::HeaderValue::from_str(value)
+            .map_err(|e| format!("Invalid header value for {}: {}", key, e))?;
+        headers.append(name, value);
+    }
+
+    let redirect = if options.max_redirects == 0 {
+        reqwest::redirect::Policy::none()
+    } else {
+        // reqwest 0.12.15 的 Policy::limited 少跟随一次，这里按上限精确计数
+        let max = options.max_redirects as usize;
+        reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > max {
+                attempt.error(format!("too many redirects (max {})", max))
+            } else {
+                attempt.follow()
+            }
+        })
+    };
+
+    let mut builder = reqwest::ClientBuilder::new()
+        .user_agent(options.user_agent.as_deref().unwrap_or("vielpork"))
+        .default_headers(headers)
+        .redirect(redirect)
+        .danger_accept_invalid_certs(!options.tls_verify);
+
+    // 超时只限制建立连接和两次读取之间的间隔，不限制整个下载的耗时
+    if options.timeout > 0 {
+        let timeout = std::time::Duration::from_secs(options.timeout);
+        builder = builder.connect_timeout(timeout).read_timeout(timeout);
+    }
+
+    // 支持 http://、https://、socks5:// 与 socks5h:// 代理
+    if let Some(proxy) = options.proxy.as_deref().filter(|p| !p.is_empty()) {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+
+    Ok(builder.build()?)
+}
+
+// Convenience function for multi-download
+pub async fn download_urls(
+    resources: Vec<DownloadResource>,
+    options: DownloadOptions,
+    resolver: Box<dyn ResourceResolver>,
+    reporter: Box<dyn CombinedReporter>,
+) -> Result<()> {
+    let store = Box::new(JsonStateStore::in_dir(&options.save_path));
+    let downloader = Downloader::new(options, resolver, reporter, store);
+    downloader.download_multi(resources).await
+}
+
+// Quick multi-download with default settings
+pub async fn quick_download_multi(
     resources: Vec<DownloadResource>,
     resolver: Box<dyn ResourceResolver>,
     reporter: Box<dyn CombinedReporter>,
@@ -866,15 +2698,1079 @@ pub async fn quick_download_multi(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::base::enums::{ProgressEvent, RetentionPolicy};
+    use crate::reporters::cli_boardcast_mpsc::CliReporterBoardcastMpsc;
     use crate::reporters::tui::TuiReporter;
     use crate::resolvers::url::UrlResolver;
+    use crate::stores::memory::MemoryStateStore;
+    use tokio::io::AsyncReadExt;
     use tokio::sync::Mutex;
 
+    /// 进程内的HTTP服务器：响应体的第一块立即发送，其余部分等到 gate 打开后发送
+    async fn gated_server(body: Vec<u8>) -> (String, tokio::sync::watch::Sender<bool>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (gate, gate_rx) = tokio::sync::watch::channel(false);
+        let body = Arc::new(body);
+
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let (body, mut gate_rx) = (body.clone(), gate_rx.clone());
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match socket.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    let head = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    );
+                    let (first, rest) = body.split_at(1024);
+                    if socket.write_all(head.as_bytes()).await.is_err()
+                        || socket.write_all(first).await.is_err()
+                    {
+                        return;
+                    }
+                    if gate_rx.wait_for(|open| *open).await.is_ok() {
+                        socket.write_all(rest).await.ok();
+                    }
+                });
+            }
+        });
+        (url, gate)
+    }
+
+    /// 支持范围请求的测试服务器，响应的前1024字节立即发送，其余部分等到 gate 打开后发送
+    struct RangeServer {
+        url: String,
+        gate: tokio::sync::watch::Sender<bool>,
+        /// 当前的ETag，If-Range与之不匹配时返回完整内容
+        etag: Arc<std::sync::Mutex<String>>,
+        /// 收到的GET请求的Range头
+        ranges: Arc<std::sync::Mutex<Vec<Option<String>>>>,
+        /// 收到的所有请求的原始请求头
+        requests: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    async fn range_server(body: Vec<u8>) -> RangeServer {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (gate, gate_rx) = tokio::sync::watch::channel(false);
+        let etag = Arc::new(std::sync::Mutex::new("\"v1\"".to_string()));
+        let ranges = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let body = Arc::new(body);
+
+        let server = RangeServer {
+            url,
+            gate,
+            etag: etag.clone(),
+            ranges: ranges.clone(),
+            requests: requests.clone(),
+        };
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let (body, mut gate_rx) = (body.clone(), gate_rx.clone());
+                let (etag, ranges, requests) = (etag.clone(), ranges.clone(), requests.clone());
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match socket.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    let request = String::from_utf8_lossy(&request).to_string();
+                    requests.lock().unwrap().push(request.clone());
+
+                    // /redirect/N 依次重定向到 /redirect/N-1，直到 /redirect/0 返回内容
+                    let path = request.split_whitespace().nth(1).unwrap_or("/");
+                    let hops = path
+                        .strip_prefix("/redirect/")
+                        .and_then(|hops| hops.parse::<u32>().ok());
+                    if let Some(hops @ 1..) = hops {
+                        let head = format!(
+                            "HTTP/1.1 302 Found\r\nLocation: /redirect/{}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                            hops - 1
+                        );
+                        socket.write_all(head.as_bytes()).await.ok();
+                        return;
+                    }
+
+                    let header = |name: &str| {
+                        request.lines().find_map(|line| {
+                            let (key, value) = line.split_once(':')?;
+                            key.trim()
+                                .eq_ignore_ascii_case(name)
+                                .then(|| value.trim().to_string())
+                        })
+                    };
+                    let etag = etag.lock().unwrap().clone();
+                    let range = header("Range");
+                    let head_only = request.starts_with("HEAD");
+                    if !head_only {
+                        ranges.lock().unwrap().push(range.clone());
+                    }
+
+                    let range = range
+                        .as_deref()
+                        .and_then(|range| range.strip_prefix("bytes="))
+                        .and_then(|range| range.split_once('-'))
+                        .map(|(start, end)| {
+                            let start: usize = start.parse().unwrap();
+                            let end = end.parse::<usize>().map_or(body.len(), |end| end + 1);
+                            (start, end.min(body.len()))
+                        })
+                        .filter(|_| header("If-Range").is_none_or(|value| value == etag));
+                    let (status, start, end) = match range {
+                        Some((start, end)) => ("206 Partial Content", start, end),
+                        None => ("200 OK", 0, body.len()),
+                    };
+                    let mut head = format!(
+                        "HTTP/1.1 {}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\nETag: {}\r\nConnection: close\r\n",
+                        status,
+                        end - start,
+                        etag
+                    );
+                    if range.is_some() {
+                        head += &format!(
+                            "Content-Range: bytes {}-{}/{}\r\n",
+                            start,
+                            end - 1,
+                            body.len()
+                        );
+                    }
+                    head += "\r\n";
+                    if socket.write_all(head.as_bytes()).await.is_err() || head_only {
+                        return;
+                    }
+
+                    let (first, rest) = body[start..end].split_at((end - start).min(1024));
+                    if socket.write_all(first).await.is_err() {
+                        return;
+                    }
+                    if gate_rx.wait_for(|open| *open).await.is_ok() {
+                        socket.write_all(rest).await.ok();
+                    }
+                });
+            }
+        });
+        server
+    }
+
+    impl RangeServer {
+        /// 该服务器的测试下载保存到的临时目录，按端口区分
+        fn save_path(&self) -> String {
+            let port = self.url.rsplit(':').next().unwrap();
+            std::env::temp_dir()
+                .join(format!("vielpork-test-{}", port))
+                .to_string_lossy()
+                .to_string()
+        }
+    }
+
+    /// 下载到服务器临时目录的测试下载器，默认每个任务一个连接，状态保存在内存中
+    fn test_downloader(
+        server: &RangeServer,
+        cfg: impl FnOnce(DownloadOptions) -> DownloadOptions,
+    ) -> (Downloader, tokio::sync::mpsc::Receiver<ProgressEvent>) {
+        test_downloader_with_store(server, MemoryStateStore::new(), cfg)
+    }
+
+    fn test_downloader_with_store(
+        server: &RangeServer,
+        store: impl StateStore + 'static,
+        cfg: impl FnOnce(DownloadOptions) -> DownloadOptions,
+    ) -> (Downloader, tokio::sync::mpsc::Receiver<ProgressEvent>) {
+        let reporter = CliReporterBoardcastMpsc::new(1024);
+        let events = reporter.subscribe_mpsc();
+        let options = DownloadOptions::default()
+            .with_save_path(server.save_path())
+            .with_connections_per_task(1);
+        let downloader = Downloader::new(
+            cfg(options),
+            Box::new(UrlResolver::new()),
+            Box::new(reporter),
+            Box::new(store),
+        );
+        (downloader, events)
+    }
+
+    async fn next_event(
+        events: &mut tokio::sync::mpsc::Receiver<ProgressEvent>,
+        filter: impl Fn(&ProgressEvent) -> bool,
+    ) -> ProgressEvent {
+        tokio::time::timeout(tokio::time::Duration::from_secs(5), async {
+            loop {
+                let event = events.recv().await.unwrap();
+                if filter(&event) {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("event not received")
+    }
+
+    #[tokio::test]
+    async fn test_build_client() {
+        let body: Vec<u8> = (0..4096).map(|i| i as u8).collect();
+        let server = range_server(body.clone()).await;
+        let options = DownloadOptions {
+            max_redirects: 2,
+            ..DownloadOptions::default()
+                .with_user_agent("vielpork-test/1.0")
+                .with_header("X-Test", "one")
+                .with_header("Authorization", "Bearer token")
+                .with_timeout(1)
+        };
+        let client = build_client(&options).unwrap();
+
+        // 重定向次数不超过上限时跟随到最终内容，每个请求都带上配置的请求头
+        server.gate.send_replace(true);
+        let response = client
+            .get(format!("{}/redirect/2", server.url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.bytes().await.unwrap().as_ref(), body.as_slice());
+        let requests = server.requests.lock().unwrap().clone();
+        assert_eq!(requests.len(), 3);
+        for request in &requests {
+            let request = request.to_ascii_lowercase();
+            assert!(request.contains("user-agent: vielpork-test/1.0\r\n"));
+            assert!(request.contains("x-test: one\r\n"));
+            assert!(request.contains("authorization: bearer token\r\n"));
+        }
+
+        // 重定向链超过上限时请求失败
+        let error = client
+            .get(format!("{}/redirect/3", server.url))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(error.is_redirect());
+
+        // 超时设置作用于读取，服务器停止发送后请求在超时后失败
+        server.gate.send_replace(false);
+        let error = client
+            .get(format!("{}/file.bin", server.url))
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap_err();
+        assert!(error.is_timeout());
+
+        // 未配置User-Agent时使用默认值
+        let client = build_client(&DownloadOptions {
+            user_agent: None,
+            ..DownloadOptions::default()
+        })
+        .unwrap();
+        client
+            .get(format!("{}/file.bin", server.url))
+            .send()
+            .await
+            .unwrap();
+        let request = server
+            .requests
+            .lock()
+            .unwrap()
+            .last()
+            .unwrap()
+            .to_ascii_lowercase();
+        assert!(request.contains("user-agent: vielpork\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_pause_resume() {
+        let body: Vec<u8> = (0..256 * 1024).map(|i| i as u8).collect();
+        let server = range_server(body.clone()).await;
+        let (downloader, mut events) = test_downloader(&server, |options| options);
+        let handle = downloader
+            .enqueue(DownloadResource::Url(format!("{}/file.bin", server.url)))
+            .await
+            .unwrap();
+
+        // 暂停单个任务时断开连接，恢复后从断点发起范围请求
+        next_event(&mut events, |e| matches!(e, ProgressEvent::Update { .. })).await;
+        downloader.pause_task(handle.id()).await.unwrap();
+        server.gate.send_replace(true);
+        next_event(&mut events, |e| {
+            matches!(e, ProgressEvent::OperationResult { message, .. } if message.starts_with("Connection released"))
+        })
+        .await;
+        let task = downloader.find_task(handle.id()).await.unwrap();
+        let released_at = task.progress.lock().await.bytes_downloaded;
+        assert!(released_at < body.len() as u64);
+        server.gate.send_replace(false);
+        downloader.resume_task(handle.id()).await.unwrap();
+        next_event(&mut events, |e| matches!(e, ProgressEvent::Update { .. })).await;
+        assert_eq!(
+            server.ranges.lock().unwrap().last().cloned().flatten(),
+            Some(format!("bytes={}-", released_at))
+        );
+
+        // 挂起下载器时保持连接，新到达的数据块使任务停在暂停处
+        downloader.pause().await.unwrap();
+        server.gate.send_replace(true);
+        tokio::time::timeout(tokio::time::Duration::from_secs(5), async {
+            while *task.state.read().await != TaskState::Paused {
+                tokio::time::sleep(tokio::time::Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(task.progress.lock().await.bytes_downloaded < body.len() as u64);
+
+        // 恢复立即生效，不需要等待轮询间隔
+        let resumed_at = tokio::time::Instant::now();
+        downloader.resume().await.unwrap();
+        let finish = next_event(&mut events, |e| matches!(e, ProgressEvent::Finish { .. })).await;
+        assert!(resumed_at.elapsed() < tokio::time::Duration::from_millis(500));
+        match finish {
+            ProgressEvent::Finish {
+                finish: DownloadResult::Success { path, .. },
+                ..
+            } => assert_eq!(tokio::fs::read(path).await.unwrap(), body),
+            other => panic!("unexpected event: {:?}", other),
+        }
+        tokio::fs::remove_dir_all(server.save_path()).await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_task_handle() {
+        let body: Vec<u8> = (0..256 * 1024).map(|i| i as u8).collect();
+        let server = range_server(body.clone()).await;
+        let url = server.url.clone();
+        let (downloader, mut events) =
+            test_downloader(&server, |options| options.with_concurrency(1));
+        let active = downloader
+            .submit(DownloadResource::Url(format!("{}/active.bin", url)))
+            .await;
+        let queued = downloader
+            .submit(DownloadResource::Url(format!("{}/queued.bin", url)))
+            .await;
+
+        let mut progress = active.progress();
+        tokio::time::timeout(
+            tokio::time::Duration::from_secs(5),
+            progress.wait_for(|p| p.bytes_downloaded == 1024),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        // 排队中的任务直接出队
+        queued.cancel().await.unwrap();
+        assert!(matches!(queued.await, DownloadResult::Canceled));
+
+        // 默认的暂停方式断开连接并让出名额，恢复后重新取得名额并续传
+        active.pause().await.unwrap();
+        server.gate.send_replace(true);
+        next_event(&mut events, |e| {
+            matches!(e, ProgressEvent::OperationResult { message, .. } if message.starts_with("Connection released"))
+        })
+        .await;
+        active.resume().await.unwrap();
+        let result = tokio::time::timeout(tokio::time::Duration::from_secs(5), active)
+            .await
+            .unwrap();
+        match result {
+            DownloadResult::Success { path, .. } => {
+                assert_eq!(tokio::fs::read(path).await.unwrap(), body)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(progress.borrow().bytes_downloaded, body.len() as u64);
+        assert!(
+            server
+                .ranges
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|range| range.is_some())
+        );
+
+        // 关闭后提交的任务通过句柄收到错误
+        downloader.shutdown(ShutdownMode::Abort).await.unwrap();
+        let rejected = downloader
+            .submit(DownloadResource::Url(format!("{}/late.bin", url)))
+            .await;
+        assert!(matches!(rejected.await, DownloadResult::Failed { .. }));
+        tokio::fs::remove_dir_all(server.save_path()).await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_resume_released_slot() {
+        let body: Vec<u8> = (0..256 * 1024).map(|i| (i / 7) as u8).collect();
+        let server = range_server(body.clone()).await;
+        let (downloader, mut events) =
+            test_downloader(&server, |options| options.with_concurrency(1));
+        let handle = downloader
+            .submit(DownloadResource::Url(format!("{}/file.bin", server.url)))
+            .await;
+
+        // 暂停后断开连接并让出唯一的名额，恢复时需要重新取得名额
+        next_event(&mut events, |e| matches!(e, ProgressEvent::Update { .. })).await;
+        handle.pause().await.unwrap();
+        server.gate.send_replace(true);
+        next_event(&mut events, |e| {
+            matches!(e, ProgressEvent::OperationResult { message, .. } if message.starts_with("Connection released"))
+        })
+        .await;
+        handle.resume().await.unwrap();
+
+        let result = tokio::time::timeout(tokio::time::Duration::from_secs(5), handle)
+            .await
+            .expect("resumed task did not finish");
+        match result {
+            DownloadResult::Success { path, .. } => {
+                assert_eq!(tokio::fs::read(path).await.unwrap(), body)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        // 恢复后从断点发起了范围请求
+        assert!(
+            server
+                .ranges
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|range| range.is_some())
+        );
+        tokio::fs::remove_dir_all(server.save_path()).await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_dispatcher_exit() {
+        let body: Vec<u8> = (0..64 * 1024).map(|i| (i / 31) as u8).collect();
+        let server = range_server(body.clone()).await;
+        server.gate.send_replace(true);
+        let (downloader, _events) = test_downloader(&server, |options| options);
+        let wait = async |done: &dyn Fn() -> bool| {
+            tokio::time::timeout(tokio::time::Duration::from_secs(5), async {
+                while !done() {
+                    tokio::time::sleep(tokio::time::Duration::from_millis(5)).await;
+                }
+            })
+            .await
+            .expect("dispatcher did not exit");
+        };
+
+        let handle = downloader
+            .submit(DownloadResource::Url(format!("{}/first.bin", server.url)))
+            .await;
+        assert!(matches!(handle.await, DownloadResult::Success { .. }));
+
+        // 关闭后分派循环结束，重新 init 后提交的任务启动新的分派循环
+        downloader.shutdown(ShutdownMode::Drain).await.unwrap();
+        wait(&|| downloader.dispatcher.lock().unwrap().is_none()).await;
+        downloader.init().await.unwrap();
+        let handle = downloader
+            .submit(DownloadResource::Url(format!("{}/second.bin", server.url)))
+            .await;
+        assert!(matches!(handle.await, DownloadResult::Success { .. }));
+
+        // 分派循环不持有下载器，释放所有克隆后循环结束
+        let inner = Arc::downgrade(&downloader.inner);
+        let scheduler = downloader.scheduler.clone();
+        drop(downloader);
+        wait(&|| inner.upgrade().is_none() && Arc::strong_count(&scheduler) == 1).await;
+        tokio::fs::remove_dir_all(server.save_path()).await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_pause_while_suspended() {
+        let body: Vec<u8> = (0..256 * 1024).map(|i| (i / 3) as u8).collect();
+        let server = range_server(body.clone()).await;
+        let (downloader, mut events) = test_downloader(&server, |options| options);
+        let handle = downloader
+            .submit(DownloadResource::Url(format!("{}/file.bin", server.url)))
+            .await;
+
+        next_event(&mut events, |e| matches!(e, ProgressEvent::Update { .. })).await;
+        downloader.pause().await.unwrap();
+        server.gate.send_replace(true);
+        let task = downloader.find_task(handle.id()).await.unwrap();
+        tokio::time::timeout(tokio::time::Duration::from_secs(5), async {
+            while *task.state.read().await != TaskState::Paused {
+                tokio::time::sleep(tokio::time::Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        // 挂起期间单独暂停的任务在下载器恢复后仍保持暂停，并断开连接
+        handle.pause().await.unwrap();
+        downloader.resume().await.unwrap();
+        next_event(&mut events, |e| {
+            matches!(e, ProgressEvent::OperationResult { message, .. } if message.starts_with("Connection released"))
+        })
+        .await;
+        assert_eq!(*task.state.read().await, TaskState::Paused);
+
+        handle.resume().await.unwrap();
+        let result = tokio::time::timeout(tokio::time::Duration::from_secs(5), handle)
+            .await
+            .unwrap();
+        match result {
+            DownloadResult::Success { path, .. } => {
+                assert_eq!(tokio::fs::read(path).await.unwrap(), body)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        tokio::fs::remove_dir_all(server.save_path()).await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_if_range_changed() {
+        let body: Vec<u8> = (0..256 * 1024).map(|i| (i / 11) as u8).collect();
+        let server = range_server(body.clone()).await;
+        let (downloader, mut events) = test_downloader(&server, |options| options);
+        let handle = downloader
+            .submit(DownloadResource::Url(format!("{}/file.bin", server.url)))
+            .await;
+
+        next_event(&mut events, |e| matches!(e, ProgressEvent::Update { .. })).await;
+        handle.pause().await.unwrap();
+        server.gate.send_replace(true);
+        next_event(&mut events, |e| {
+            matches!(e, ProgressEvent::OperationResult { message, .. } if message.starts_with("Connection released"))
+        })
+        .await;
+        let part = PartFile::new(&std::path::Path::new(&server.save_path()).join("file.bin"));
+        assert!(part.resume_len().await.unwrap() > 0);
+
+        // 暂停期间远程文件变化，If-Range不匹配时服务器返回完整内容，临时文件从头写入
+        *server.etag.lock().unwrap() = "\"v2\"".to_string();
+        handle.resume().await.unwrap();
+        next_event(&mut events, |e| {
+            matches!(e, ProgressEvent::OperationResult { message, .. } if message.contains("restarting from the beginning"))
+        })
+        .await;
+        let result = tokio::time::timeout(tokio::time::Duration::from_secs(5), handle)
+            .await
+            .unwrap();
+        match result {
+            DownloadResult::Success { path, size, .. } => {
+                assert_eq!(size, body.len() as u64);
+                assert_eq!(tokio::fs::read(path).await.unwrap(), body)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        tokio::fs::remove_dir_all(server.save_path()).await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_if_range_changed_segmented() {
+        let body: Vec<u8> = (0..256 * 1024).map(|i| (i / 29) as u8).collect();
+        let server = range_server(body.clone()).await;
+        let (downloader, mut events) = test_downloader(&server, |options| {
+            options
+                .with_connections_per_task(4)
+                .with_chunk_size(64 * 1024)
+        });
+        let handle = downloader
+            .submit(DownloadResource::Url(format!("{}/file.bin", server.url)))
+            .await;
+
+        // 四个分段各自收到第一块数据后暂停并断开连接
+        tokio::time::timeout(
+            tokio::time::Duration::from_secs(5),
+            handle
+                .progress()
+                .wait_for(|p| p.bytes_downloaded == 4 * 1024),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        handle.pause().await.unwrap();
+        server.gate.send_replace(true);
+        next_event(&mut events, |e| {
+            matches!(e, ProgressEvent::OperationResult { message, .. } if message.starts_with("Connection released"))
+        })
+        .await;
+
+        // 远程文件变化后分段的范围请求收到完整内容，丢弃已写入的数据并从头分段下载
+        *server.etag.lock().unwrap() = "\"v2\"".to_string();
+        let requests = server.ranges.lock().unwrap().len();
+        handle.resume().await.unwrap();
+        next_event(&mut events, |e| {
+            matches!(e, ProgressEvent::OperationResult { message, .. } if message.contains("restarting from the beginning"))
+        })
+        .await;
+        let result = tokio::time::timeout(tokio::time::Duration::from_secs(5), handle)
+            .await
+            .unwrap();
+        match result {
+            DownloadResult::Success { path, size, .. } => {
+                assert_eq!(size, body.len() as u64);
+                assert_eq!(tokio::fs::read(path).await.unwrap(), body)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let ranges = server.ranges.lock().unwrap()[requests..].to_vec();
+        assert!(ranges.contains(&Some("bytes=0-65535".to_string())));
+        assert!(ranges.contains(&Some("bytes=196608-262143".to_string())));
+        tokio::fs::remove_dir_all(server.save_path()).await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_shared_download() {
+        let body: Vec<u8> = (0..64 * 1024).map(|i| (i / 17) as u8).collect();
+        let server = range_server(body.clone()).await;
+        server.gate.send_replace(true);
+        let (downloader, mut events) = test_downloader(&server, |options| options);
+
+        // 同一批中的相同资源只下载一次，每个任务各自收到结果
+        let url = format!("{}/shared.bin", server.url);
+        downloader
+            .run(vec![
+                DownloadResource::Url(url.clone()),
+                DownloadResource::Url(url.clone()),
+            ])
+            .await
+            .unwrap();
+        let mut finished = Vec::new();
+        let mut shared = false;
+        while finished.len() < 2 {
+            match next_event(&mut events, |e| {
+                matches!(
+                    e,
+                    ProgressEvent::OperationResult { .. } | ProgressEvent::Finish { .. }
+                )
+            })
+            .await
+            {
+                ProgressEvent::Finish {
+                    task_id,
+                    finish: DownloadResult::Success { path, .. },
+                } => {
+                    assert_eq!(tokio::fs::read(path).await.unwrap(), body);
+                    finished.push(task_id);
+                }
+                ProgressEvent::OperationResult { message, .. } => {
+                    shared |= message.starts_with("Same resource as task");
+                }
+                other => panic!("unexpected event: {:?}", other),
+            }
+        }
+        assert_eq!(finished.len(), 2);
+        assert_ne!(finished[0], finished[1]);
+        assert!(shared);
+        // 一次预请求与一次下载
+        assert_eq!(server.ranges.lock().unwrap().len(), 2);
+        tokio::fs::remove_dir_all(server.save_path()).await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_shared_cancel() {
+        let body: Vec<u8> = (0..64 * 1024).map(|i| (i / 19) as u8).collect();
+        let server = range_server(body.clone()).await;
+        let (downloader, _events) = test_downloader(&server, |options| options);
+        let url = format!("{}/shared.bin", server.url);
+        let first = downloader.submit(DownloadResource::Url(url.clone())).await;
+        let second = downloader.submit(DownloadResource::Url(url.clone())).await;
+        let third = downloader.submit(DownloadResource::Url(url.clone())).await;
+        tokio::time::timeout(
+            tokio::time::Duration::from_secs(5),
+            third.progress().wait_for(|p| p.bytes_downloaded == 1024),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        // 取消执行下载的任务与另一个等待者，只是退出共享，下载继续进行
+        first.cancel().await.unwrap();
+        assert!(matches!(first.await, DownloadResult::Canceled));
+        second.cancel().await.unwrap();
+        assert!(matches!(second.await, DownloadResult::Canceled));
+        server.gate.send_replace(true);
+        match tokio::time::timeout(tokio::time::Duration::from_secs(5), third)
+            .await
+            .unwrap()
+        {
+            DownloadResult::Success { path, .. } => {
+                assert_eq!(tokio::fs::read(path).await.unwrap(), body)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(server.ranges.lock().unwrap().len(), 2);
+        tokio::fs::remove_dir_all(server.save_path()).await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_skip_completed() {
+        use crate::base::enums::FileChecksum;
+        use crate::hash::{HashAlgorithm, StreamingHasher, to_hex};
+
+        let body: Vec<u8> = (0..64 * 1024).map(|i| (i / 23) as u8).collect();
+        let server = range_server(body.clone()).await;
+        server.gate.send_replace(true);
+
+        let save_path = server.save_path();
+        let url = format!("{}/done.bin", server.url);
+        let file_path = std::path::Path::new(&save_path).join("done.bin");
+        tokio::fs::create_dir_all(&save_path).await.unwrap();
+        tokio::fs::write(&file_path, &body).await.unwrap();
+        let mut hasher = StreamingHasher::new(HashAlgorithm::Sha256);
+        hasher.update(&body);
+        let resource = DownloadResource::Resolved(ResolvedResource {
+            id: 0,
+            url: url.clone(),
+            headers: Vec::new(),
+            auth: None,
+            checksum: Some(FileChecksum::SHA256(to_hex(&hasher.finalize()))),
+            priority: 0,
+        });
+        // 上次会话已完成的记录
+        let record = TaskStateRecord {
+            id: TaskId::new(1),
+            url: url.clone(),
+            key: content_key(&url),
+            downloaded_bytes: body.len() as u64,
+            total_bytes: Some(body.len() as u64),
+            file_path: file_path.clone(),
+            state: TaskState::Completed,
+            etag: None,
+            last_modified: None,
+            resource_id: 0,
+            headers: Vec::new(),
+            auth: None,
+            checksum: None,
+            segments: Vec::new(),
+        };
+
+        let run = async |resource: DownloadResource| {
+            let store = MemoryStateStore::new();
+            store
+                .upsert_all(std::slice::from_ref(&record))
+                .await
+                .unwrap();
+            let (downloader, events) =
+                test_downloader_with_store(&server, store, |options| options);
+            downloader.run(vec![resource]).await.unwrap();
+            events
+        };
+        let message = |pattern: &'static str| move |e: &ProgressEvent| matches!(e, ProgressEvent::OperationResult { message, .. } if message.contains(pattern));
+
+        // 文件完整且通过校验时跳过，不发起请求
+        let mut events = run(resource.clone()).await;
+        next_event(&mut events, message("Skipped, already downloaded")).await;
+        assert!(server.ranges.lock().unwrap().is_empty());
+
+        // 大小相同但内容损坏的文件重新下载
+        let mut corrupt = body.clone();
+        corrupt[100] ^= 0xff;
+        tokio::fs::write(&file_path, &corrupt).await.unwrap();
+        let mut events = run(resource).await;
+        next_event(
+            &mut events,
+            message("was completed in a previous session but"),
+        )
+        .await;
+        assert!(!server.ranges.lock().unwrap().is_empty());
+        assert_eq!(tokio::fs::read(&file_path).await.unwrap(), body);
+        tokio::fs::remove_dir_all(save_path).await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stalled() {
+        let save_path =
+            std::env::temp_dir().join(format!("vielpork-cancel-{}", std::process::id()));
+        // gate 不会打开，第一块数据之后连接一直停滞
+        let (url, _gate) = gated_server(vec![0; 64 * 1024]).await;
+
+        let reporter = CliReporterBoardcastMpsc::new(1024);
+        let mut events = reporter.subscribe_mpsc();
+        let downloader = Downloader::new(
+            DownloadOptions::default()
+                .with_save_path(save_path.to_string_lossy().to_string())
+                .with_connections_per_task(1)
+                .with_concurrency(2),
+            Box::new(UrlResolver::new()),
+            Box::new(reporter.clone()),
+            Box::new(MemoryStateStore::new()),
+        );
+        let first = downloader
+            .enqueue(DownloadResource::Url(format!("{}/first.bin", url)))
+            .await
+            .unwrap();
+        let second = downloader
+            .enqueue(DownloadResource::Url(format!("{}/second.bin", url)))
+            .await
+            .unwrap();
+        for _ in 0..2 {
+            next_event(&mut events, |e| matches!(e, ProgressEvent::Start { .. })).await;
+        }
+
+        // 返回时任务已经结束，取消的结果已经报告
+        let timeout = tokio::time::Duration::from_secs(2);
+        tokio::time::timeout(timeout, downloader.cancel_task(first.id()))
+            .await
+            .unwrap()
+            .unwrap();
+        let finished = |id: TaskId| move |e: &ProgressEvent| matches!(e, ProgressEvent::Finish { task_id, finish: DownloadResult::Canceled } if *task_id == id);
+        next_event(&mut events, finished(first.id())).await;
+
+        tokio::time::timeout(timeout, downloader.stop())
+            .await
+            .unwrap()
+            .unwrap();
+        next_event(&mut events, finished(second.id())).await;
+        tokio::fs::remove_dir_all(save_path).await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let save_path = std::env::temp_dir()
+            .join(format!("vielpork-shutdown-{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let body = vec![7; 64 * 1024];
+        let (url, gate) = gated_server(body.clone()).await;
+
+        let reporter = CliReporterBoardcastMpsc::new(1024);
+        let mut events = reporter.subscribe_mpsc();
+        let new_downloader = || {
+            Downloader::new(
+                DownloadOptions::default()
+                    .with_save_path(save_path.clone())
+                    .with_connections_per_task(1)
+                    .with_concurrency(1),
+                Box::new(UrlResolver::new()),
+                Box::new(reporter.clone()),
+                Box::new(JsonStateStore::in_dir(&save_path)),
+            )
+        };
+
+        // Checkpoint：停滞中的任务保存准确的进度，排队中的任务被丢弃
+        let downloader = new_downloader();
+        let active = downloader
+            .enqueue(DownloadResource::Url(format!("{}/a.bin", url)))
+            .await
+            .unwrap();
+        let queued = downloader
+            .enqueue(DownloadResource::Url(format!("{}/b.bin", url)))
+            .await
+            .unwrap();
+        next_event(&mut events, |e| matches!(e, ProgressEvent::Update { .. })).await;
+        let summary = tokio::time::timeout(
+            tokio::time::Duration::from_secs(2),
+            downloader.shutdown(ShutdownMode::Checkpoint),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(summary.persisted, vec![active.id()]);
+        assert_eq!(summary.dropped, vec![queued.id()]);
+        assert!(summary.completed.is_empty());
+        assert!(matches!(
+            downloader
+                .enqueue(DownloadResource::Url(format!("{}/c.bin", url)))
+                .await
+                .unwrap_err()
+                .kind(),
+            ErrorKind::ShuttingDown
+        ));
+
+        let store = JsonStateStore::in_dir(&save_path);
+        let state = store.load().await.unwrap().unwrap();
+        assert_eq!(state.tasks.len(), 1);
+        assert_eq!(state.tasks[0].state, TaskState::Paused);
+        assert_eq!(state.tasks[0].downloaded_bytes, 1024);
+
+        // Drain：续传的任务在关闭期间下载完成，状态随之清空
+        let downloader = new_downloader();
+        let resumed = downloader.resume_session(store.path()).await.unwrap();
+        next_event(&mut events, |e| matches!(e, ProgressEvent::Update { .. })).await;
+        let shutdown = tokio::spawn({
+            let downloader = downloader.clone();
+            async move { downloader.shutdown(ShutdownMode::Drain).await }
+        });
+        // 让关闭先清空队列，再放行剩余的数据
+        tokio::task::yield_now().await;
+        gate.send_replace(true);
+        let summary = tokio::time::timeout(tokio::time::Duration::from_secs(2), shutdown)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(summary.completed, vec![resumed[0].id()]);
+        assert!(summary.persisted.is_empty() && summary.dropped.is_empty());
+        assert_eq!(
+            tokio::fs::read(std::path::Path::new(&save_path).join("a.bin"))
+                .await
+                .unwrap(),
+            body
+        );
+        assert!(store.load().await.unwrap().is_none());
+        tokio::fs::remove_dir_all(save_path).await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_retention_store() {
+        let body: Vec<u8> = (0..64 * 1024).map(|i| (i / 5) as u8).collect();
+        let finished = range_server(body.clone()).await;
+        let stalled = range_server(body.clone()).await;
+        let save_path = finished.save_path();
+        let new_downloader = || {
+            test_downloader_with_store(&finished, JsonStateStore::in_dir(&save_path), |options| {
+                options
+                    .with_concurrency(2)
+                    .with_retention(RetentionPolicy::KeepLast(0))
+            })
+        };
+
+        // 两个任务都在下载中时保存状态，之后其中一个完成并被移出任务表
+        let (downloader, _events) = new_downloader();
+        let done = downloader
+            .submit(DownloadResource::Url(format!("{}/done.bin", finished.url)))
+            .await;
+        let pending = downloader
+            .submit(DownloadResource::Url(format!(
+                "{}/pending.bin",
+                stalled.url
+            )))
+            .await;
+        for handle in [&done, &pending] {
+            tokio::time::timeout(
+                tokio::time::Duration::from_secs(5),
+                handle.progress().wait_for(|p| p.bytes_downloaded == 1024),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        }
+        downloader.save_state().await.unwrap();
+        let done_id = done.id();
+        finished.gate.send_replace(true);
+        assert!(matches!(done.await, DownloadResult::Success { .. }));
+        tokio::time::timeout(tokio::time::Duration::from_secs(5), async {
+            while downloader.get_tasks().await.iter().any(|t| t.id == done_id) {
+                tokio::time::sleep(tokio::time::Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        downloader.shutdown(ShutdownMode::Checkpoint).await.unwrap();
+
+        // 续传时只恢复未完成的任务，已完成并被移除的任务不会重新下载
+        let store = JsonStateStore::in_dir(&save_path);
+        let state = store.load().await.unwrap().unwrap();
+        assert_eq!(state.tasks.len(), 1);
+        assert!(state.tasks[0].url.ends_with("/pending.bin"));
+        let (downloader, _events) = new_downloader();
+        let resumed = downloader.resume_session(store.path()).await.unwrap();
+        assert_eq!(resumed.len(), 1);
+        stalled.gate.send_replace(true);
+        let resumed = resumed.into_iter().next().unwrap();
+        assert!(matches!(resumed.await, DownloadResult::Success { .. }));
+        tokio::fs::remove_dir_all(save_path).await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_resume_damaged_part() {
+        let body: Vec<u8> = (0..64 * 1024).map(|i| (i / 13) as u8).collect();
+        let server = range_server(body.clone()).await;
+        let save_path = server.save_path();
+        let new_downloader = || {
+            test_downloader_with_store(&server, JsonStateStore::in_dir(&save_path), |options| {
+                options.with_concurrency(2)
+            })
+        };
+
+        let (downloader, _events) = new_downloader();
+        for name in ["missing.bin", "short.bin"] {
+            let handle = downloader
+                .submit(DownloadResource::Url(format!("{}/{}", server.url, name)))
+                .await;
+            tokio::time::timeout(
+                tokio::time::Duration::from_secs(5),
+                handle.progress().wait_for(|p| p.bytes_downloaded == 1024),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        }
+        let summary = downloader.shutdown(ShutdownMode::Checkpoint).await.unwrap();
+        assert_eq!(summary.persisted.len(), 2);
+
+        // 会话之间临时文件被删除或截断
+        let dir = std::path::Path::new(&save_path);
+        tokio::fs::remove_file(PartFile::new(&dir.join("missing.bin")).path())
+            .await
+            .unwrap();
+        let short = PartFile::new(&dir.join("short.bin"));
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(short.path())
+            .await
+            .unwrap();
+        file.set_len(512).await.unwrap();
+        drop(file);
+
+        let (downloader, mut events) = new_downloader();
+        let store = JsonStateStore::in_dir(&save_path);
+        let resumed = downloader.resume_session(store.path()).await.unwrap();
+        assert_eq!(resumed.len(), 2);
+        let mut messages = Vec::new();
+        let mut offsets = Vec::new();
+        while offsets.len() < 2 {
+            match next_event(&mut events, |e| {
+                matches!(
+                    e,
+                    ProgressEvent::OperationResult { .. } | ProgressEvent::Start { .. }
+                )
+            })
+            .await
+            {
+                ProgressEvent::OperationResult { message, .. } => messages.push(message),
+                ProgressEvent::Start { offset, .. } => offsets.push(offset),
+                _ => unreachable!(),
+            }
+        }
+        assert!(messages.iter().any(|m| {
+            m.starts_with("Partial file of")
+                && m.contains("missing.bin")
+                && m.ends_with("restarted from the beginning")
+        }));
+        assert!(messages.iter().any(|m| {
+            m.contains("short.bin")
+                && m.ends_with("has 512 of 1024 recorded bytes, resuming from 512")
+        }));
+        offsets.sort();
+        assert_eq!(offsets, vec![0, 512]);
+
+        server.gate.send_replace(true);
+        for handle in resumed {
+            match tokio::time::timeout(tokio::time::Duration::from_secs(5), handle)
+                .await
+                .unwrap()
+            {
+                DownloadResult::Success { path, .. } => {
+                    assert_eq!(tokio::fs::read(path).await.unwrap(), body)
+                }
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+        tokio::fs::remove_dir_all(save_path).await.ok();
+    }
+
     #[tokio::test]
     async fn test_download_single() {
         let options = DownloadOptions::default().with_save_path("fetch".to_string());
 
-        let resources = vec![
+        let resources = [
             DownloadResource::Url("https://www.google.com".to_string()),
             DownloadResource::Url("https://www.bing.com".to_string()),
             DownloadResource::Url("https://www.baidu.com".to_string()),
@@ -884,9 +3780,11 @@ mod tests {
             options,
             Box::new(UrlResolver::new()),
             Box::new(TuiReporter::new()),
+            Box::new(JsonStateStore::in_dir("fetch")),
         );
+        let slot = downloader.scheduler.acquire().await.unwrap();
         downloader
-            .download_task(resources[0].clone())
+            .download_task(downloader.allocate_id(), resources[0].clone(), slot)
             .await
             .unwrap();
     }
@@ -903,6 +3801,7 @@ mod tests {
             options,
             Box::new(UrlResolver::new()),
             Box::new(TuiReporter::new()),
+            Box::new(JsonStateStore::in_dir("fetch")),
         );
         downloader.start(resources).await.unwrap();
     }
@@ -920,6 +3819,7 @@ mod tests {
             options,
             Box::new(UrlResolver::new()),
             Box::new(TuiReporter::new()),
+            Box::new(JsonStateStore::in_dir("fetch")),
         )));
 
         let downloader_clone = Arc::clone(&downloader);
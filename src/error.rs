@@ -10,6 +10,38 @@ impl Error {
             inner: Box::new(kind),
         }
     }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.inner
+    }
+
+    /// 判断错误是否为暂时性错误（连接失败、超时、5xx、传输中断等），可以重试
+    pub fn is_retryable(&self) -> bool {
+        match self.kind() {
+            ErrorKind::HttpStatus { code, .. } => is_retryable_status(*code),
+            ErrorKind::SizeMismatch { .. } => true,
+            ErrorKind::ReqwestError(e) => match e.status() {
+                Some(status) => is_retryable_status(status.as_u16()),
+                None => {
+                    e.is_timeout()
+                        || e.is_connect()
+                        || e.is_request()
+                        || e.is_body()
+                        || e.is_decode()
+                }
+            },
+            ErrorKind::StdIoError(e) => matches!(
+                e.kind(),
+                std::io::ErrorKind::Interrupted
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
 }
 
 impl std::fmt::Debug for Error {
@@ -24,7 +56,29 @@ impl std::fmt::Display for Error {
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self.kind() {
+            ErrorKind::ReqwestError(e) => Some(e),
+            ErrorKind::StdIoError(e) => Some(e),
+            ErrorKind::SerdeJsonError(e) => Some(e),
+            ErrorKind::HandlebarsRenderError(e) => Some(e),
+            ErrorKind::ChronoParseError(e) => Some(e),
+            ErrorKind::TokioMpscSendError(e) => Some(e),
+            ErrorKind::TokioBroadcastSendError(e) => Some(e),
+            #[cfg(feature = "tui")]
+            ErrorKind::IndicatifTemplateError(e) => Some(e),
+            #[cfg(feature = "sqlite")]
+            ErrorKind::SqliteError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// 5xx、408、429 属于暂时性错误
+fn is_retryable_status(code: u16) -> bool {
+    (500..600).contains(&code) || code == 408 || code == 429
+}
 
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Self {
@@ -81,6 +135,12 @@ impl From<indicatif::style::TemplateError> for Error {
         Error::new(ErrorKind::IndicatifTemplateError(e))
     }
 }
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::new(ErrorKind::SqliteError(e))
+    }
+}
 impl From<String> for Error {
     fn from(e: String) -> Self {
         Error::new(ErrorKind::VielporkError(e))
@@ -95,6 +155,41 @@ impl From<&str> for Error {
 
 pub enum ErrorKind {
     VielporkError(String),
+    /// 服务器返回了非成功状态码，或不符合预期的状态码（如范围请求未返回206）
+    HttpStatus {
+        code: u16,
+        url: String,
+        /// 429/503响应中Retry-After头要求的等待秒数
+        retry_after: Option<u64>,
+    },
+    /// 续传过程中远程文件发生了变化（If-Range不匹配）
+    RemoteChanged {
+        url: String,
+    },
+    TaskNotFound(crate::base::structs::TaskId),
+    /// 任务被取消或下载器已停止，进行中的操作被放弃
+    Canceled,
+    /// 下载器正在关闭，不再接受新任务
+    ShuttingDown,
+    /// 无效的路径策略配置（命名、目录组织、冲突处理）
+    InvalidPolicy(String),
+    /// 目标文件已存在且冲突策略为 error
+    PathConflict(std::path::PathBuf),
+    SizeMismatch {
+        expected: u64,
+        actual: u64,
+    },
+    ChecksumMismatch {
+        expected: String,
+        actual: String,
+    },
+    /// 状态文件的版本比当前程序支持的更新
+    UnsupportedStateVersion(u32),
+    /// 任务或下载器的非法状态转换
+    InvalidStateTransition {
+        from: String,
+        to: String,
+    },
     ReqwestError(reqwest::Error),
     StdIoError(std::io::Error),
     SerdeJsonError(serde_json::Error),
@@ -106,12 +201,43 @@ pub enum ErrorKind {
     ),
     #[cfg(feature = "tui")]
     IndicatifTemplateError(indicatif::style::TemplateError),
+    #[cfg(feature = "sqlite")]
+    SqliteError(rusqlite::Error),
 }
 
 impl std::fmt::Debug for ErrorKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ErrorKind::VielporkError(e) => write!(f, "{}", e),
+            ErrorKind::HttpStatus { code, url, .. } => {
+                write!(f, "HTTP error: {} for {}", code, url)
+            }
+            ErrorKind::RemoteChanged { url } => {
+                write!(f, "Remote file changed during download: {}", url)
+            }
+            ErrorKind::TaskNotFound(id) => write!(f, "Task {} not found", id),
+            ErrorKind::Canceled => write!(f, "Operation canceled"),
+            ErrorKind::ShuttingDown => write!(f, "Downloader is shutting down"),
+            ErrorKind::InvalidPolicy(e) => write!(f, "Invalid policy: {}", e),
+            ErrorKind::PathConflict(path) => {
+                write!(f, "File already exists: {}", path.display())
+            }
+            ErrorKind::SizeMismatch { expected, actual } => write!(
+                f,
+                "Downloaded size mismatch: expected {}, got {}",
+                expected, actual
+            ),
+            ErrorKind::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Checksum mismatch: expected {}, got {}",
+                expected, actual
+            ),
+            ErrorKind::UnsupportedStateVersion(version) => {
+                write!(f, "Unsupported state file version: {}", version)
+            }
+            ErrorKind::InvalidStateTransition { from, to } => {
+                write!(f, "Cannot transition from {} to {}", from, to)
+            }
             ErrorKind::ReqwestError(e) => write!(f, "{}", e),
             ErrorKind::StdIoError(e) => write!(f, "{}", e),
             ErrorKind::SerdeJsonError(e) => write!(f, "{}", e),
@@ -122,6 +248,8 @@ impl std::fmt::Debug for ErrorKind {
 
             #[cfg(feature = "tui")]
             ErrorKind::IndicatifTemplateError(e) => write!(f, "{}", e),
+            #[cfg(feature = "sqlite")]
+            ErrorKind::SqliteError(e) => write!(f, "{}", e),
         }
     }
 }
@@ -130,6 +258,35 @@ impl std::fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ErrorKind::VielporkError(e) => write!(f, "{}", e),
+            ErrorKind::HttpStatus { code, url, .. } => {
+                write!(f, "HTTP error: {} for {}", code, url)
+            }
+            ErrorKind::RemoteChanged { url } => {
+                write!(f, "Remote file changed during download: {}", url)
+            }
+            ErrorKind::TaskNotFound(id) => write!(f, "Task {} not found", id),
+            ErrorKind::Canceled => write!(f, "Operation canceled"),
+            ErrorKind::ShuttingDown => write!(f, "Downloader is shutting down"),
+            ErrorKind::InvalidPolicy(e) => write!(f, "Invalid policy: {}", e),
+            ErrorKind::PathConflict(path) => {
+                write!(f, "File already exists: {}", path.display())
+            }
+            ErrorKind::SizeMismatch { expected, actual } => write!(
+                f,
+                "Downloaded size mismatch: expected {}, got {}",
+                expected, actual
+            ),
+            ErrorKind::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Checksum mismatch: expected {}, got {}",
+                expected, actual
+            ),
+            ErrorKind::UnsupportedStateVersion(version) => {
+                write!(f, "Unsupported state file version: {}", version)
+            }
+            ErrorKind::InvalidStateTransition { from, to } => {
+                write!(f, "Cannot transition from {} to {}", from, to)
+            }
             ErrorKind::ReqwestError(e) => write!(f, "{}", e),
             ErrorKind::StdIoError(e) => write!(f, "{}", e),
             ErrorKind::SerdeJsonError(e) => write!(f, "{}", e),
@@ -139,6 +296,39 @@ impl std::fmt::Display for ErrorKind {
             ErrorKind::TokioBroadcastSendError(e) => write!(f, "TokioBroadcastSendError: {}", e),
             #[cfg(feature = "tui")]
             ErrorKind::IndicatifTemplateError(e) => write!(f, "{}", e),
+            #[cfg(feature = "sqlite")]
+            ErrorKind::SqliteError(e) => write!(f, "{}", e),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn test_error_classification() {
+        let status = |code| {
+            Error::new(ErrorKind::HttpStatus {
+                code,
+                url: "http://example.com".into(),
+                retry_after: None,
+            })
+        };
+        assert!(status(503).is_retryable());
+        assert!(status(429).is_retryable());
+        assert!(!status(404).is_retryable());
+        assert!(
+            !Error::new(ErrorKind::TaskNotFound(crate::base::structs::TaskId::new(
+                1
+            )))
+            .is_retryable()
+        );
+
+        let io: Error = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "eof").into();
+        assert!(io.is_retryable());
+        assert!(io.source().is_some());
+        assert!(status(500).source().is_none());
+    }
+}
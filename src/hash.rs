@@ -0,0 +1,211 @@
+use crate::base::enums::FileChecksum;
+use crate::error::{Error, ErrorKind, Result};
+use md5::Digest;
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// 支持的摘要算法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().replace('-', "").as_str() {
+            "md5" => Some(HashAlgorithm::Md5),
+            "sha1" => Some(HashAlgorithm::Sha1),
+            "sha256" => Some(HashAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+}
+
+impl FileChecksum {
+    pub fn algorithm(&self) -> Result<HashAlgorithm> {
+        match self {
+            FileChecksum::MD5(_) => Ok(HashAlgorithm::Md5),
+            FileChecksum::SHA1(_) => Ok(HashAlgorithm::Sha1),
+            FileChecksum::SHA256(_) => Ok(HashAlgorithm::Sha256),
+            FileChecksum::Custom { algorithm, .. } => HashAlgorithm::from_name(algorithm)
+                .ok_or_else(|| format!("Unsupported checksum algorithm: {}", algorithm).into()),
+        }
+    }
+
+    pub fn value(&self) -> &str {
+        match self {
+            FileChecksum::MD5(value)
+            | FileChecksum::SHA1(value)
+            | FileChecksum::SHA256(value)
+            | FileChecksum::Custom { value, .. } => value,
+        }
+    }
+
+    /// 比较摘要，期望值可以是十六进制（不区分大小写）或 Base64 编码
+    pub fn matches(&self, digest: &[u8]) -> bool {
+        let expected = self.value().trim();
+        if expected.eq_ignore_ascii_case(&to_hex(digest)) {
+            return true;
+        }
+        base64_simd::STANDARD
+            .decode_to_vec(expected)
+            .is_ok_and(|decoded| decoded == digest)
+    }
+}
+
+/// 增量计算摘要
+#[derive(Clone)]
+pub enum StreamingHasher {
+    Md5(md5::Md5),
+    Sha1(sha1::Sha1),
+    Sha256(sha2::Sha256),
+}
+
+impl StreamingHasher {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Md5 => StreamingHasher::Md5(md5::Md5::new()),
+            HashAlgorithm::Sha1 => StreamingHasher::Sha1(sha1::Sha1::new()),
+            HashAlgorithm::Sha256 => StreamingHasher::Sha256(sha2::Sha256::new()),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            StreamingHasher::Md5(h) => h.update(data),
+            StreamingHasher::Sha1(h) => h.update(data),
+            StreamingHasher::Sha256(h) => h.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            StreamingHasher::Md5(h) => h.finalize().to_vec(),
+            StreamingHasher::Sha1(h) => h.finalize().to_vec(),
+            StreamingHasher::Sha256(h) => h.finalize().to_vec(),
+        }
+    }
+}
+
+/// 下载过程中的校验器
+///
+/// 只有紧接在已计算部分之后的数据块会被直接计算；
+/// 断点续传时磁盘上已有的部分，以及分段下载中乱序到达的部分，在 catch_up 时从文件读回补算
+pub struct ChecksumVerifier {
+    checksum: FileChecksum,
+    hasher: StreamingHasher,
+    position: u64,
+}
+
+impl ChecksumVerifier {
+    pub fn new(checksum: FileChecksum) -> Result<Self> {
+        let hasher = StreamingHasher::new(checksum.algorithm()?);
+        Ok(Self {
+            checksum,
+            hasher,
+            position: 0,
+        })
+    }
+
+    /// 已经计算过摘要的字节数（从文件开头连续）
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// 写入位于 offset 的数据块；不连续的数据块会被忽略，留给 catch_up 处理
+    pub fn feed(&mut self, offset: u64, data: &[u8]) {
+        if offset == self.position {
+            self.hasher.update(data);
+            self.position += data.len() as u64;
+        }
+    }
+
+    /// 文件被截断重新下载时，重置摘要状态
+    pub fn reset(&mut self) -> Result<()> {
+        self.hasher = StreamingHasher::new(self.checksum.algorithm()?);
+        self.position = 0;
+        Ok(())
+    }
+
+    /// 从文件中读回 [position, end) 并计算摘要
+    pub async fn catch_up(&mut self, file_path: &Path, end: u64) -> Result<()> {
+        if self.position >= end {
+            return Ok(());
+        }
+        let mut file = tokio::fs::File::open(file_path).await?;
+        file.seek(std::io::SeekFrom::Start(self.position)).await?;
+
+        let mut buffer = vec![0u8; 64 * 1024];
+        while self.position < end {
+            let want = buffer.len().min((end - self.position) as usize);
+            let read = file.read(&mut buffer[..want]).await?;
+            if read == 0 {
+                break;
+            }
+            self.hasher.update(&buffer[..read]);
+            self.position += read as u64;
+        }
+        Ok(())
+    }
+
+    /// 完成计算并与期望值比较
+    pub fn verify(self) -> Result<()> {
+        let digest = self.hasher.finalize();
+        if self.checksum.matches(&digest) {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::ChecksumMismatch {
+                expected: self.checksum.value().to_string(),
+                actual: to_hex(&digest),
+            }))
+        }
+    }
+}
+
+/// 计算整个文件的摘要并校验
+pub async fn verify_file(file_path: &Path, checksum: &FileChecksum) -> Result<()> {
+    let mut verifier = ChecksumVerifier::new(checksum.clone())?;
+    let len = tokio::fs::metadata(file_path).await?.len();
+    verifier.catch_up(file_path, len).await?;
+    verifier.verify()
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum_matches() {
+        let mut hasher = StreamingHasher::new(HashAlgorithm::Sha256);
+        hasher.update(b"hello ");
+        hasher.update(b"world");
+        let digest = hasher.finalize();
+
+        let hex = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+        assert!(FileChecksum::SHA256(hex.to_uppercase()).matches(&digest));
+        assert!(
+            FileChecksum::SHA256("uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek=".into())
+                .matches(&digest)
+        );
+        assert!(!FileChecksum::SHA256("00".into()).matches(&digest));
+    }
+
+    #[test]
+    fn test_verifier_skips_out_of_order_chunks() {
+        let mut verifier =
+            ChecksumVerifier::new(FileChecksum::MD5("5eb63bbbe01eeed093cb22bb8f5acdc3".into()))
+                .unwrap();
+        verifier.feed(0, b"hello");
+        verifier.feed(6, b"world");
+        assert_eq!(verifier.position(), 5);
+        verifier.feed(5, b" ");
+        verifier.feed(6, b"world");
+        assert!(verifier.verify().is_ok());
+    }
+}
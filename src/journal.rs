@@ -0,0 +1,112 @@
+use crate::error::Result;
+use crate::task::PersistentState;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::io::AsyncWriteExt;
+
+/// 保存在 save_path 下的状态文件名
+pub const STATE_FILE: &str = "downloading.json";
+
+/// 状态保存的协调：短时间内来自多个任务的保存请求合并为一次写入，同一时间只有一次写入
+///
+/// 同时提供状态文件的读写：写入先落到临时文件再重命名，进程中途退出也不会留下残缺的文件
+#[derive(Debug, Default)]
+pub struct StateJournal {
+    /// 有尚未写入的变化
+    dirty: AtomicBool,
+    /// 已安排了一次延迟写入
+    scheduled: AtomicBool,
+    /// 同一时间只有一次写入，避免旧快照覆盖新快照
+    write: tokio::sync::Mutex<()>,
+}
+
+impl StateJournal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn path(save_path: &str) -> PathBuf {
+        PathBuf::from(save_path).join(STATE_FILE)
+    }
+
+    /// 标记有变化，返回调用方是否需要安排一次延迟写入
+    pub fn mark_dirty(&self) -> bool {
+        self.dirty.store(true, Ordering::SeqCst);
+        !self.scheduled.swap(true, Ordering::SeqCst)
+    }
+
+    /// 延迟写入开始执行，返回期间是否仍有变化需要写入
+    pub fn take_scheduled(&self) -> bool {
+        self.scheduled.store(false, Ordering::SeqCst);
+        self.dirty.load(Ordering::SeqCst)
+    }
+
+    /// 获取写入权，持有期间生成快照并写入
+    pub async fn lock(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        let guard = self.write.lock().await;
+        // 即将写入的快照包含此前的所有变化
+        self.dirty.store(false, Ordering::SeqCst);
+        guard
+    }
+
+    pub async fn load(path: &Path) -> Result<Option<PersistentState>> {
+        match tokio::fs::read_to_string(path).await {
+            Ok(contents) => PersistentState::from_json(&contents).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// 原子地写入状态文件：先写临时文件并同步到磁盘，再重命名覆盖
+    pub async fn write(path: &Path, state: &PersistentState) -> Result<()> {
+        let contents = serde_json::to_vec(state)?;
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+
+        let mut file = tokio::fs::File::create(&temp).await?;
+        file.write_all(&contents).await?;
+        file.sync_all().await?;
+        drop(file);
+        tokio::fs::rename(&temp, path).await?;
+        Ok(())
+    }
+
+    pub async fn remove(path: &Path) -> Result<()> {
+        match tokio::fs::remove_file(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_journal_write() {
+        let dir = std::env::temp_dir().join(format!("vielpork-journal-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join(STATE_FILE);
+
+        StateJournal::write(&path, &PersistentState::new(Vec::new()))
+            .await
+            .unwrap();
+        let state = StateJournal::load(&path).await.unwrap().unwrap();
+        assert!(state.tasks.is_empty());
+        assert!(!dir.join("downloading.json.tmp").exists());
+
+        let journal = StateJournal::new();
+        assert!(journal.mark_dirty());
+        assert!(!journal.mark_dirty());
+        assert!(journal.take_scheduled());
+        drop(journal.lock().await);
+        assert!(!journal.take_scheduled());
+
+        StateJournal::remove(&path).await.unwrap();
+        StateJournal::remove(&path).await.unwrap();
+        assert!(StateJournal::load(&path).await.unwrap().is_none());
+        tokio::fs::remove_dir_all(&dir).await.ok();
+    }
+}
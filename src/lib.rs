@@ -3,7 +3,14 @@
 pub mod base;
 pub mod downloader;
 pub mod error;
+pub mod hash;
+pub mod journal;
+pub mod limiter;
+pub mod part;
+pub mod registry;
 pub mod reporters;
 pub mod resolvers;
+pub mod scheduler;
+pub mod stores;
 pub mod task;
 pub mod template;
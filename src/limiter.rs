@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use crate::base::algorithms::url_host;
+use crate::base::structs::HostPolicy;
+
+/// 单次等待的最长时间，保证限速调整能够尽快生效
+const MAX_WAIT: Duration = Duration::from_millis(100);
+
+/// 令牌桶限速器，速率单位为字节/秒，None 表示不限速
+///
+/// 桶容量等于一秒的配额，速率可以在下载过程中随时调整
+#[derive(Debug)]
+pub struct RateLimiter {
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    rate: Option<u64>,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        if let Some(rate) = self.rate {
+            let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * rate as f64).min(rate as f64);
+        }
+        self.last_refill = now;
+    }
+}
+
+impl RateLimiter {
+    pub fn new(rate: Option<u64>) -> Self {
+        Self {
+            bucket: Mutex::new(Bucket {
+                rate: rate.filter(|r| *r > 0),
+                tokens: rate.unwrap_or(0) as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn rate(&self) -> Option<u64> {
+        self.bucket.lock().unwrap().rate
+    }
+
+    pub fn set_rate(&self, rate: Option<u64>) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.refill();
+        bucket.rate = rate.filter(|r| *r > 0);
+        if let Some(rate) = bucket.rate {
+            bucket.tokens = bucket.tokens.min(rate as f64);
+        }
+    }
+
+    /// 获取 amount 个字节的配额，配额不足时等待
+    ///
+    /// 超过桶容量的请求会在桶满时放行并透支，后续请求需等待透支部分被补回
+    pub async fn acquire(&self, amount: u64) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                bucket.refill();
+                let Some(rate) = bucket.rate else {
+                    return;
+                };
+                let needed = amount.min(rate) as f64;
+                if bucket.tokens >= needed {
+                    bucket.tokens -= amount as f64;
+                    return;
+                }
+                Duration::from_secs_f64((needed - bucket.tokens) / rate as f64)
+            };
+            tokio::time::sleep(wait.min(MAX_WAIT)).await;
+        }
+    }
+}
+
+/// 按主机限制并发连接数与请求间隔
+///
+/// 同一主机的连接数达到上限时等待其他连接结束，两次请求之间至少间隔 min_delay；
+/// 收到 Retry-After 后该主机的所有请求推迟到指定时间之后。策略可以在下载过程中随时调整
+#[derive(Debug, Default)]
+pub struct HostLimiter {
+    inner: Mutex<HostLimiterInner>,
+    /// 连接结束或策略变化时通知等待者
+    changed: Notify,
+}
+
+#[derive(Debug, Default)]
+struct HostLimiterInner {
+    policy: HostPolicy,
+    hosts: HashMap<String, HostState>,
+}
+
+#[derive(Debug)]
+struct HostState {
+    active: u32,
+    /// 下一次请求最早的开始时间
+    next_request: Instant,
+}
+
+/// 占用中的主机连接，drop 时归还
+#[derive(Debug)]
+pub struct HostPermit {
+    limiter: Arc<HostLimiter>,
+    host: Option<String>,
+}
+
+impl HostLimiter {
+    pub fn new(policy: HostPolicy) -> Self {
+        Self {
+            inner: Mutex::new(HostLimiterInner {
+                policy,
+                hosts: HashMap::new(),
+            }),
+            changed: Notify::new(),
+        }
+    }
+
+    pub fn set_policy(&self, policy: HostPolicy) {
+        self.inner.lock().unwrap().policy = policy;
+        self.changed.notify_waiters();
+    }
+
+    /// 等待并占用 url 所在主机的一个连接，无法解析主机名的 url 不受限制
+    pub async fn acquire(self: &Arc<Self>, url: &str) -> HostPermit {
+        let Some(host) = url_host(url) else {
+            return HostPermit {
+                limiter: self.clone(),
+                host: None,
+            };
+        };
+
+        loop {
+            let notified = self.changed.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            let wait = {
+                let mut inner = self.inner.lock().unwrap();
+                let limit = inner.policy.limit_for(&host);
+                let delay = Duration::from_millis(inner.policy.min_delay);
+                let now = Instant::now();
+                let state = inner.hosts.entry(host.clone()).or_insert(HostState {
+                    active: 0,
+                    next_request: now,
+                });
+
+                if limit.is_some_and(|limit| state.active >= limit) {
+                    None
+                } else if state.next_request > now {
+                    Some(state.next_request - now)
+                } else {
+                    state.active += 1;
+                    state.next_request = now + delay;
+                    return HostPermit {
+                        limiter: self.clone(),
+                        host: Some(host),
+                    };
+                }
+            };
+
+            match wait {
+                Some(wait) => tokio::time::sleep(wait.min(MAX_WAIT)).await,
+                None => notified.await,
+            }
+        }
+    }
+
+    /// 推迟 url 所在主机的后续请求，用于遵守 Retry-After
+    pub fn defer(&self, url: &str, delay: Duration) {
+        let Some(host) = url_host(url) else {
+            return;
+        };
+        let mut inner = self.inner.lock().unwrap();
+        let until = Instant::now() + delay;
+        let state = inner.hosts.entry(host).or_insert(HostState {
+            active: 0,
+            next_request: until,
+        });
+        state.next_request = state.next_request.max(until);
+    }
+}
+
+impl Drop for HostPermit {
+    fn drop(&mut self) {
+        let Some(host) = self.host.take() else {
+            return;
+        };
+        {
+            let mut inner = self.limiter.inner.lock().unwrap();
+            if let Some(state) = inner.hosts.get_mut(&host) {
+                state.active -= 1;
+                // 没有连接也不需要等待的主机不再保留记录
+                if state.active == 0 && state.next_request <= Instant::now() {
+                    inner.hosts.remove(&host);
+                }
+            }
+        }
+        self.limiter.changed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rate_limiter() {
+        let limiter = RateLimiter::new(Some(1000));
+
+        // 初始桶是满的，第一秒的配额可以立即获取
+        let start = Instant::now();
+        limiter.acquire(1000).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        limiter.acquire(500).await;
+        assert!(start.elapsed() >= Duration::from_millis(450));
+
+        limiter.set_rate(None);
+        let start = Instant::now();
+        limiter.acquire(1_000_000).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn test_host_limiter() {
+        let limiter = Arc::new(HostLimiter::new(
+            HostPolicy::new()
+                .with_max_connections(1)
+                .with_min_delay(100),
+        ));
+
+        let start = Instant::now();
+        let first = limiter.acquire("http://a.com/1").await;
+        // 其他主机不受影响
+        drop(limiter.acquire("http://b.com/1").await);
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        // 连接数已满，等第一个连接结束
+        let waiter = {
+            let limiter = limiter.clone();
+            tokio::spawn(async move { drop(limiter.acquire("http://a.com/2").await) })
+        };
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!waiter.is_finished());
+        drop(first);
+        waiter.await.unwrap();
+
+        // 放宽限制后立即生效，但仍需遵守请求间隔
+        limiter.set_policy(HostPolicy::new().with_min_delay(100));
+        let start = Instant::now();
+        let _a = limiter.acquire("http://a.com/3").await;
+        let _b = limiter.acquire("http://a.com/4").await;
+        assert!(start.elapsed() >= Duration::from_millis(90));
+
+        limiter.defer("http://c.com/", Duration::from_millis(200));
+        let start = Instant::now();
+        drop(limiter.acquire("http://c.com/1").await);
+        assert!(start.elapsed() >= Duration::from_millis(190));
+    }
+}
@@ -1,3 +1,433 @@
-fn main() {
-    println!("Hello, world!");
+use async_trait::async_trait;
+use clap::Parser;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use tokio::sync::Mutex;
+
+use vielpork::base::enums::{DownloadResource, DownloadResult, OperationType, ShutdownMode};
+use vielpork::base::structs::{DownloadOptions, DownloadProgress, HostPolicy, PathPolicy, TaskId};
+use vielpork::base::traits::{ProgressReporter, ResultReporter};
+use vielpork::downloader::Downloader;
+use vielpork::error::Result;
+use vielpork::reporters::tui::TuiReporter;
+use vielpork::resolvers::url::UrlResolver;
+use vielpork::stores::json::JsonStateStore;
+
+/// 全部下载成功
+const EXIT_SUCCESS: u8 = 0;
+/// 至少一个下载失败或被取消
+const EXIT_FAILURE: u8 = 1;
+/// 参数或配置错误（与 clap 的解析错误一致）
+const EXIT_USAGE: u8 = 2;
+/// 被 Ctrl-C 中断
+const EXIT_INTERRUPTED: u8 = 130;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "vielpork",
+    version,
+    about = "A multi-threaded HTTP downloader",
+    after_help = "Exit codes: 0 all downloads succeeded, 1 at least one download failed or was \
+                  canceled, 2 invalid arguments, 130 interrupted by Ctrl-C."
+)]
+struct Args {
+    /// URLs to download
+    urls: Vec<String>,
+
+    /// Read URLs from a file, one per line ("-" for stdin, "#" starts a comment)
+    #[arg(short, long, value_name = "FILE")]
+    input_file: Option<PathBuf>,
+
+    /// Directory to save downloads to
+    #[arg(short = 'o', long, value_name = "DIR", default_value = "downloads")]
+    save_path: String,
+
+    /// Maximum number of concurrent downloads
+    #[arg(short = 'c', long, default_value_t = 4)]
+    concurrency: u32,
+
+    /// Maximum connections per download when the server supports ranges
+    #[arg(long, default_value_t = 4)]
+    connections: u32,
+
+    /// Maximum connections to a single host
+    #[arg(long, value_name = "N")]
+    per_host_connections: Option<u32>,
+
+    /// Minimum delay between requests to the same host, in milliseconds
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    host_delay: u64,
+
+    /// File name template, e.g. "{{filename}}_{{date}}.{{ext}}"
+    #[arg(long, value_name = "TEMPLATE")]
+    naming_template: Option<String>,
+
+    /// Directory organization: flat, by_type, by_domain or custom
+    #[arg(long, default_value = "flat")]
+    organization: String,
+
+    /// Directory template used with --organization custom
+    #[arg(long, value_name = "TEMPLATE")]
+    dir_template: Option<String>,
+
+    /// What to do when the target file exists: overwrite, rename or error
+    #[arg(long, default_value = "overwrite")]
+    conflict: String,
+
+    /// Global rate limit in bytes per second (accepts K, M and G suffixes)
+    #[arg(long, value_name = "RATE", value_parser = parse_size)]
+    rate_limit: Option<u64>,
+
+    /// Per-connection rate limit in bytes per second (accepts K, M and G suffixes)
+    #[arg(long, value_name = "RATE", value_parser = parse_size)]
+    per_connection_rate_limit: Option<u64>,
+
+    /// Proxy URL (http, https or socks5)
+    #[arg(long)]
+    proxy: Option<String>,
+
+    /// Retries for transient failures
+    #[arg(long, default_value_t = 3)]
+    retries: u32,
+
+    /// Connect and read timeout in seconds
+    #[arg(long, default_value_t = 30)]
+    timeout: u64,
+
+    /// Extra request header, e.g. "Authorization: Bearer xxx" (repeatable)
+    #[arg(short = 'H', long = "header", value_name = "HEADER")]
+    headers: Vec<String>,
+
+    /// User-Agent header
+    #[arg(long)]
+    user_agent: Option<String>,
+
+    /// Skip TLS certificate verification
+    #[arg(long)]
+    insecure: bool,
+
+    /// Resume unfinished downloads recorded in <SAVE_PATH>/downloading.json
+    #[arg(long)]
+    resume: bool,
+
+    /// Restart partially downloaded files from the beginning instead of resuming them
+    #[arg(long, conflicts_with = "resume")]
+    no_resume: bool,
+}
+
+/// 解析带 K/M/G 后缀（1024 进制）的字节数
+fn parse_size(value: &str) -> std::result::Result<u64, String> {
+    let value = value.trim();
+    let (number, unit) = match value.char_indices().find(|(_, c)| c.is_ascii_alphabetic()) {
+        Some((index, _)) => value.split_at(index),
+        None => (value, ""),
+    };
+    let multiplier: u64 = match unit.to_ascii_uppercase().trim_end_matches(['B', 'I']) {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(format!("unknown unit in {:?}", value)),
+    };
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid size {:?}", value))?;
+    if number < 0.0 {
+        return Err(format!("invalid size {:?}", value));
+    }
+    Ok((number * multiplier as f64) as u64)
+}
+
+impl Args {
+    fn options(&self) -> std::result::Result<DownloadOptions, String> {
+        let mut policy = PathPolicy::default()
+            .with_organization(&self.organization)
+            .with_conflict(&self.conflict);
+        if let Some(template) = &self.naming_template {
+            policy = policy.with_naming("custom").with_template(template);
+        }
+        if let Some(template) = &self.dir_template {
+            policy = policy.with_dir_template(template);
+        }
+
+        let mut host_policy = HostPolicy::new().with_min_delay(self.host_delay);
+        if let Some(n) = self.per_host_connections {
+            host_policy = host_policy.with_max_connections(n);
+        }
+
+        let mut options = DownloadOptions::default()
+            .with_save_path(&self.save_path)
+            .with_path_policy(policy)
+            .with_concurrency(self.concurrency)
+            .with_connections_per_task(self.connections)
+            .with_host_policy(host_policy)
+            .with_max_retries(self.retries)
+            .with_timeout(self.timeout)
+            .with_tls_verify(!self.insecure)
+            .with_resume_download(!self.no_resume);
+        if let Some(limit) = self.rate_limit {
+            options = options.with_rate_limit(limit);
+        }
+        if let Some(limit) = self.per_connection_rate_limit {
+            options = options.with_per_connection_rate_limit(limit);
+        }
+        if let Some(proxy) = &self.proxy {
+            options = options.with_proxy(proxy);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            options = options.with_user_agent(user_agent);
+        }
+        for header in &self.headers {
+            let (key, value) = header
+                .split_once(':')
+                .ok_or_else(|| format!("invalid header {:?}, expected \"Name: value\"", header))?;
+            options = options.with_header(key.trim(), value.trim());
+        }
+        Ok(options)
+    }
+
+    /// 收集命令行与 URL 文件中的 URL，去除重复项
+    async fn urls(&self) -> std::result::Result<Vec<String>, String> {
+        let mut urls = self.urls.clone();
+
+        if let Some(path) = &self.input_file {
+            let contents = if path.as_os_str() == "-" {
+                let mut contents = String::new();
+                tokio::io::stdin()
+                    .read_to_string(&mut contents)
+                    .await
+                    .map_err(|e| format!("failed to read stdin: {}", e))?;
+                contents
+            } else {
+                tokio::fs::read_to_string(path)
+                    .await
+                    .map_err(|e| format!("failed to read {}: {}", path.display(), e))?
+            };
+            urls.extend(
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(str::to_string),
+            );
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        urls.retain(|url| seen.insert(url.clone()));
+        Ok(urls)
+    }
+}
+
+/// 各任务结果的汇总
+#[derive(Debug, Default)]
+struct Summary {
+    succeeded: usize,
+    failed: usize,
+    canceled: usize,
+    /// 任务开始前就失败的资源（解析失败、请求失败等）
+    errors: usize,
+}
+
+impl Summary {
+    /// 按任务结果计算退出码，只有 Ctrl-C 中断时返回 EXIT_INTERRUPTED
+    fn exit_code(&self, interrupted: bool) -> u8 {
+        if interrupted {
+            EXIT_INTERRUPTED
+        } else if self.failed > 0 || self.errors > 0 || self.canceled > 0 {
+            EXIT_FAILURE
+        } else {
+            EXIT_SUCCESS
+        }
+    }
+}
+
+/// 在 TuiReporter 的基础上汇总任务结果，用于计算退出码
+struct CliReporter {
+    tui: TuiReporter,
+    summary: Arc<Mutex<Summary>>,
+}
+
+#[async_trait]
+impl ProgressReporter for CliReporter {
+    async fn start_task(&self, task_id: TaskId, total: Option<u64>, offset: u64) -> Result<()> {
+        self.tui.start_task(task_id, total, offset).await
+    }
+
+    async fn update_progress(&self, task_id: TaskId, progress: &DownloadProgress) -> Result<()> {
+        self.tui.update_progress(task_id, progress).await
+    }
+
+    async fn finish_task(&self, task_id: TaskId, result: DownloadResult) -> Result<()> {
+        {
+            let mut summary = self.summary.lock().await;
+            match &result {
+                DownloadResult::Success { .. } => summary.succeeded += 1,
+                DownloadResult::Failed { .. } => summary.failed += 1,
+                DownloadResult::Canceled => summary.canceled += 1,
+            }
+        }
+        self.tui.finish_task(task_id, result).await
+    }
+}
+
+#[async_trait]
+impl ResultReporter for CliReporter {
+    async fn operation_result(
+        &self,
+        operation: OperationType,
+        task_id: TaskId,
+        code: u32,
+        message: String,
+    ) -> Result<()> {
+        // 任务级的失败会通过 finish_task 汇报，这里只统计没有对应任务的失败
+        if task_id == TaskId::NONE && code != 200 && matches!(operation, OperationType::Download) {
+            self.summary.lock().await.errors += 1;
+        }
+        self.tui
+            .operation_result(operation, task_id, code, message)
+            .await
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let options = match args.options() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    let urls = match args.urls().await {
+        Ok(urls) if urls.is_empty() && !args.resume => {
+            eprintln!("error: no URLs given");
+            return ExitCode::from(EXIT_USAGE);
+        }
+        Ok(urls) => urls,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    let summary = Arc::new(Mutex::new(Summary::default()));
+    let reporter = CliReporter {
+        tui: TuiReporter::new(),
+        summary: summary.clone(),
+    };
+    let store = JsonStateStore::in_dir(&options.save_path);
+    let state_path = store.path().to_path_buf();
+    let downloader = match Downloader::try_new(
+        options,
+        Box::new(UrlResolver::new()),
+        Box::new(reporter),
+        Box::new(store),
+    ) {
+        Ok(downloader) => downloader,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    // 继续上次会话中未完成的任务，已下载的部分通过范围请求续传
+    if args.resume && state_path.exists() {
+        match downloader.resume_session(&state_path).await {
+            Ok(handles) if handles.is_empty() && urls.is_empty() => {
+                eprintln!("error: nothing to resume in {}", state_path.display());
+                return ExitCode::from(EXIT_USAGE);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("error: invalid {}: {}", state_path.display(), e);
+                return ExitCode::from(EXIT_USAGE);
+            }
+        }
+    } else if urls.is_empty() {
+        eprintln!("error: no URLs given");
+        return ExitCode::from(EXIT_USAGE);
+    }
+
+    let resources = urls.into_iter().map(DownloadResource::Url).collect();
+    let mut interrupted = false;
+    let result = tokio::select! {
+        result = downloader.run(resources) => result,
+        _ = tokio::signal::ctrl_c() => {
+            interrupted = true;
+            // 暂停所有任务并保存准确的进度，之后可以用 --resume 继续
+            downloader
+                .shutdown(ShutdownMode::Checkpoint)
+                .await
+                .map(|summary| {
+                    eprintln!(
+                        "Interrupted with {} unfinished tasks saved, run again with --resume to continue",
+                        summary.persisted.len()
+                    )
+                })
+        }
+    };
+
+    let summary = summary.lock().await;
+    println!(
+        "{} succeeded, {} failed, {} canceled",
+        summary.succeeded,
+        summary.failed + summary.errors,
+        summary.canceled
+    );
+
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        if !interrupted {
+            return ExitCode::from(EXIT_FAILURE);
+        }
+    }
+    ExitCode::from(summary.exit_code(interrupted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("2K"), Ok(2048));
+        assert_eq!(parse_size("1.5MiB"), Ok(3 << 19));
+        assert_eq!(parse_size("1g"), Ok(1 << 30));
+        assert!(parse_size("10x").is_err());
+        assert!(parse_size("-1").is_err());
+    }
+
+    #[test]
+    fn test_resume_flags() {
+        let resume_download = |args: &[&str]| {
+            Args::try_parse_from([&["vielpork"], args].concat())
+                .map(|args| args.options().unwrap().resume_download)
+        };
+        assert!(resume_download(&["https://example.com"]).unwrap());
+        assert!(resume_download(&["--resume"]).unwrap());
+        assert!(!resume_download(&["--no-resume", "https://example.com"]).unwrap());
+        assert!(resume_download(&["--resume", "--no-resume"]).is_err());
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let summary = |failed, canceled| Summary {
+            succeeded: 1,
+            failed,
+            canceled,
+            errors: 0,
+        };
+        assert_eq!(summary(0, 0).exit_code(false), EXIT_SUCCESS);
+        assert_eq!(summary(1, 0).exit_code(false), EXIT_FAILURE);
+        // 不是由 Ctrl-C 引起的取消不算作中断
+        assert_eq!(summary(0, 1).exit_code(false), EXIT_FAILURE);
+        assert_eq!(summary(0, 1).exit_code(true), EXIT_INTERRUPTED);
+        assert_eq!(summary(0, 0).exit_code(true), EXIT_INTERRUPTED);
+    }
 }
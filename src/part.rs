@@ -0,0 +1,199 @@
+use crate::error::{ErrorKind, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// 下载中的临时文件后缀
+pub const PART_SUFFIX: &str = ".vielpork.part";
+/// 续传元数据文件后缀（紧跟在临时文件名之后）
+pub const META_SUFFIX: &str = ".meta";
+
+/// 与临时文件一起保存的续传元数据
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PartMeta {
+    pub url: String,
+    pub total_size: Option<u64>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// 分段写入期间文件长度不可信，只有前 valid_len 字节有效；None 表示整个文件都有效
+    #[serde(default)]
+    pub valid_len: Option<u64>,
+}
+
+/// 目标文件对应的临时文件：数据写入 `<name>.vielpork.part`，
+/// 元数据写入 `<name>.vielpork.part.meta`，完成后再重命名为目标文件
+#[derive(Debug, Clone)]
+pub struct PartFile {
+    target: PathBuf,
+    data: PathBuf,
+    meta: PathBuf,
+}
+
+impl PartFile {
+    pub fn new(target: &Path) -> Self {
+        let mut data = target.as_os_str().to_owned();
+        data.push(PART_SUFFIX);
+        let mut meta = data.clone();
+        meta.push(META_SUFFIX);
+        Self {
+            target: target.to_path_buf(),
+            data: PathBuf::from(data),
+            meta: PathBuf::from(meta),
+        }
+    }
+
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+
+    /// 临时数据文件路径
+    pub fn path(&self) -> &PathBuf {
+        &self.data
+    }
+
+    /// 临时文件中可用于续传的字节数，多余的部分会被截断
+    pub async fn resume_len(&self) -> Result<u64> {
+        let len = match tokio::fs::metadata(&self.data).await {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        let valid_len = self
+            .load_meta()
+            .await
+            .and_then(|meta| meta.valid_len)
+            .map_or(len, |valid_len| valid_len.min(len));
+        if valid_len < len {
+            let file = tokio::fs::OpenOptions::new()
+                .write(true)
+                .open(&self.data)
+                .await?;
+            file.set_len(valid_len).await?;
+            file.sync_all().await?;
+        }
+        Ok(valid_len)
+    }
+
+    pub async fn load_meta(&self) -> Option<PartMeta> {
+        let contents = tokio::fs::read(&self.meta).await.ok()?;
+        serde_json::from_slice(&contents).ok()
+    }
+
+    pub async fn save_meta(&self, meta: &PartMeta) -> Result<()> {
+        if let Some(parent) = self.meta.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&self.meta, serde_json::to_vec(meta)?).await?;
+        Ok(())
+    }
+
+    /// 删除临时文件与元数据
+    pub async fn discard(&self) -> Result<()> {
+        for path in [&self.data, &self.meta] {
+            match tokio::fs::remove_file(path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// 将临时文件落盘后原子地重命名为目标文件，按冲突策略处理已存在的目标文件
+    ///
+    /// 返回最终的文件路径
+    pub async fn commit(&self, conflict: &str) -> Result<PathBuf> {
+        tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(&self.data)
+            .await?
+            .sync_all()
+            .await?;
+
+        let path = resolve_conflict(&self.target, conflict)?;
+        tokio::fs::rename(&self.data, &path).await?;
+        sync_dir(&path).await;
+
+        tokio::fs::remove_file(&self.meta).await.ok();
+        Ok(path)
+    }
+}
+
+/// 按冲突策略（overwrite | rename | error）确定最终路径
+pub fn resolve_conflict(path: &Path, conflict: &str) -> Result<PathBuf> {
+    let mut counter = 1;
+    let mut resolved = path.to_path_buf();
+
+    while resolved.exists() {
+        match conflict {
+            "overwrite" => {
+                break;
+            }
+            "rename" => {
+                let stem = path
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_str()
+                    .unwrap_or_default();
+                let ext = path
+                    .extension()
+                    .map(|e| format!(".{}", e.to_str().unwrap_or_default()))
+                    .unwrap_or_default();
+
+                resolved.set_file_name(format!("{}_{}{}", stem, counter, ext));
+                counter += 1;
+            }
+            "error" => {
+                return Err(ErrorKind::PathConflict(resolved).into());
+            }
+            _ => {
+                return Err(ErrorKind::InvalidPolicy(format!("conflict {}", conflict)).into());
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+/// 同步父目录，保证重命名本身落盘（仅Unix）
+async fn sync_dir(path: &Path) {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        if let Ok(dir) = tokio::fs::File::open(parent).await {
+            dir.sync_all().await.ok();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_part_commit() {
+        let dir = std::env::temp_dir().join(format!("vielpork-part-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let target = dir.join("file.bin");
+        tokio::fs::write(&target, b"old").await.unwrap();
+
+        let part = PartFile::new(&target);
+        assert_eq!(part.path(), &dir.join("file.bin.vielpork.part"));
+        tokio::fs::write(part.path(), b"new data").await.unwrap();
+        part.save_meta(&PartMeta {
+            valid_len: Some(3),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(part.resume_len().await.unwrap(), 3);
+
+        assert!(part.commit("error").await.is_err());
+        assert_eq!(part.commit("rename").await.unwrap(), dir.join("file_1.bin"));
+        assert_eq!(
+            tokio::fs::read(dir.join("file_1.bin")).await.unwrap(),
+            b"new"
+        );
+        assert!(!part.path().exists());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+use dashmap::DashMap;
+use std::collections::VecDeque;
+
+use crate::base::enums::{RetentionPolicy, TaskState};
+use crate::base::structs::TaskId;
+use crate::task::DownloadTask;
+
+/// 按ID索引的任务表
+///
+/// 读多写少，分片加锁，任何操作都不会跨越 await 持有锁；遍历时使用 snapshot 复制一份
+#[derive(Debug, Default)]
+pub struct TaskRegistry {
+    tasks: DashMap<TaskId, DownloadTask>,
+    /// 已结束并等待按保留策略移除的任务，先结束的在前
+    retired: std::sync::Mutex<VecDeque<TaskId>>,
+}
+
+impl TaskRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, task_id: TaskId) -> Option<DownloadTask> {
+        self.tasks.get(&task_id).map(|task| task.clone())
+    }
+
+    pub fn insert(&self, task: DownloadTask) {
+        self.tasks.insert(task.id, task);
+    }
+
+    pub fn remove(&self, task_id: TaskId) -> Option<DownloadTask> {
+        self.tasks.remove(&task_id).map(|(_, task)| task)
+    }
+
+    /// 移除满足条件的任务
+    pub fn remove_if(&self, f: impl Fn(&DownloadTask) -> bool) {
+        self.tasks.retain(|_, task| !f(task));
+    }
+
+    pub fn clear(&self) {
+        self.tasks.clear();
+        self.retired.lock().unwrap().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// 当前所有任务的副本，按ID（即加入的先后）排序
+    pub fn snapshot(&self) -> Vec<DownloadTask> {
+        let mut tasks: Vec<DownloadTask> =
+            self.tasks.iter().map(|task| task.value().clone()).collect();
+        tasks.sort_by_key(|task| task.id);
+        tasks
+    }
+
+    /// 任务执行结束后调用，已完成或已取消的任务按保留策略移除，返回被移除的任务
+    ///
+    /// 失败与暂停的任务仍需保存以便续传，不会被移除
+    pub async fn retire(&self, task_id: TaskId, policy: RetentionPolicy) -> Vec<DownloadTask> {
+        let Some(task) = self.get(task_id) else {
+            return Vec::new();
+        };
+        if !matches!(
+            *task.state.read().await,
+            TaskState::Completed | TaskState::Canceled
+        ) {
+            return Vec::new();
+        }
+        let keep = match policy {
+            RetentionPolicy::KeepAll => return Vec::new(),
+            RetentionPolicy::KeepLast(keep) => keep,
+        };
+
+        let mut retired = self.retired.lock().unwrap();
+        retired.retain(|id| *id != task_id);
+        retired.push_back(task_id);
+        let mut evicted = Vec::new();
+        while retired.len() > keep {
+            if let Some(id) = retired.pop_front() {
+                evicted.extend(self.remove(id));
+            }
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[tokio::test]
+    async fn test_retention() {
+        let registry = TaskRegistry::new();
+        for id in 1..=3 {
+            let task = DownloadTask::new(
+                TaskId::new(id),
+                format!("http://example.com/{}", id),
+                PathBuf::from(format!("{}.bin", id)),
+                None,
+            );
+            *task.state.write().await = if id == 2 {
+                TaskState::Failed
+            } else {
+                TaskState::Completed
+            };
+            registry.insert(task);
+        }
+
+        let mut evicted = Vec::new();
+        for id in 1..=3 {
+            evicted.extend(
+                registry
+                    .retire(TaskId::new(id), RetentionPolicy::KeepLast(1))
+                    .await,
+            );
+        }
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].id, TaskId::new(1));
+        // 失败的任务保留，已完成的任务只保留最近的一个
+        let ids: Vec<TaskId> = registry.snapshot().iter().map(|task| task.id).collect();
+        assert_eq!(ids, vec![TaskId::new(2), TaskId::new(3)]);
+    }
+}
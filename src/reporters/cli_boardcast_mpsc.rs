@@ -1,5 +1,5 @@
 use crate::base::enums::{DownloadResult, OperationType, ProgressEvent};
-use crate::base::structs::DownloadProgress;
+use crate::base::structs::{DownloadProgress, TaskId};
 use crate::base::traits::{ProgressReporter, ResultReporter};
 use crate::error::Result;
 use async_trait::async_trait;
@@ -54,12 +54,17 @@ impl CliReporterBoardcastMpsc {
 
 #[async_trait]
 impl ProgressReporter for CliReporterBoardcastMpsc {
-    async fn start_task(&self, task_id: u32, total: u64) -> Result<()> {
-        self.send(ProgressEvent::Start { task_id, total }).await?;
+    async fn start_task(&self, task_id: TaskId, total: Option<u64>, offset: u64) -> Result<()> {
+        self.send(ProgressEvent::Start {
+            task_id,
+            total,
+            offset,
+        })
+        .await?;
         Ok(())
     }
 
-    async fn update_progress(&self, task_id: u32, progress: &DownloadProgress) -> Result<()> {
+    async fn update_progress(&self, task_id: TaskId, progress: &DownloadProgress) -> Result<()> {
         self.send(ProgressEvent::Update {
             task_id,
             progress: progress.clone(),
@@ -68,7 +73,7 @@ impl ProgressReporter for CliReporterBoardcastMpsc {
         Ok(())
     }
 
-    async fn finish_task(&self, task_id: u32, finish: DownloadResult) -> Result<()> {
+    async fn finish_task(&self, task_id: TaskId, finish: DownloadResult) -> Result<()> {
         self.send(ProgressEvent::Finish { task_id, finish }).await?;
         Ok(())
     }
@@ -79,7 +84,7 @@ impl ResultReporter for CliReporterBoardcastMpsc {
     async fn operation_result(
         &self,
         operation: OperationType,
-        task_id: u32,
+        task_id: TaskId,
         code: u32,
         message: String,
     ) -> Result<()> {
@@ -1,5 +1,5 @@
 use crate::base::enums::{DownloadResult, OperationType};
-use crate::base::structs::DownloadProgress;
+use crate::base::structs::{DownloadProgress, TaskId};
 use crate::base::traits::{ProgressReporter, ResultReporter};
 use crate::error::Result;
 use async_trait::async_trait;
@@ -17,7 +17,13 @@ const MAX_CONCURRENT_BARS: usize = 10;
 #[derive(Debug)]
 pub struct TuiReporter {
     mp: MultiProgress,
-    bars: Arc<Mutex<HashMap<u32, ProgressBar>>>,
+    bars: Arc<Mutex<HashMap<TaskId, ProgressBar>>>,
+}
+
+impl Default for TuiReporter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TuiReporter {
@@ -34,8 +40,8 @@ impl TuiReporter {
         mp
     }
 
-    // 私有方法用于获取或创建进度条
-    async fn get_or_create_bar(&self, task_id: u32, total: u64) -> ProgressBar {
+    // 私有方法用于获取或创建进度条，总大小未知时使用旋转指示器
+    async fn get_or_create_bar(&self, task_id: TaskId, total: Option<u64>) -> ProgressBar {
         let mut bars = self.bars.lock().await;
 
         if bars.len() >= MAX_CONCURRENT_BARS {
@@ -43,17 +49,29 @@ impl TuiReporter {
         }
 
         bars.entry(task_id)
-            .or_insert_with(|| {
-                let bar = ProgressBar::new(total);
-                // 关键修改：将进度条添加到 MultiProgress 系统
-                let bar = self.mp.add(bar); // 这行是核心修改
-                bar.set_style(ProgressStyle::with_template(&format!(
-                    "{{spinner:.green}} [{{bar:.cyan/blue}}] {{bytes}}/{{total_bytes}} ({}) {{msg}}",
-                    task_id
-                ))
-                .unwrap_or(ProgressStyle::default_bar())
-                .progress_chars("#>-"));
-                bar
+            .or_insert_with(|| match total {
+                Some(total) => {
+                    let bar = ProgressBar::new(total);
+                    // 关键修改：将进度条添加到 MultiProgress 系统
+                    let bar = self.mp.add(bar); // 这行是核心修改
+                    bar.set_style(ProgressStyle::with_template(&format!(
+                        "{{spinner:.green}} [{{bar:.cyan/blue}}] {{bytes}}/{{total_bytes}} ({}) {{msg}}",
+                        task_id
+                    ))
+                    .unwrap_or(ProgressStyle::default_bar())
+                    .progress_chars("#>-"));
+                    bar
+                }
+                None => {
+                    let bar = self.mp.add(ProgressBar::new_spinner());
+                    bar.set_style(ProgressStyle::with_template(&format!(
+                        "{{spinner:.green}} {{bytes}} ({}) {{msg}}",
+                        task_id
+                    ))
+                    .unwrap_or(ProgressStyle::default_spinner()));
+                    bar.enable_steady_tick(std::time::Duration::from_millis(100));
+                    bar
+                }
             });
 
         bars.get(&task_id).unwrap_or(&ProgressBar::hidden()).clone()
@@ -62,33 +80,37 @@ impl TuiReporter {
 
 #[async_trait]
 impl ProgressReporter for TuiReporter {
-    async fn start_task(&self, task_id: u32, total: u64) -> Result<()> {
+    async fn start_task(&self, task_id: TaskId, total: Option<u64>, offset: u64) -> Result<()> {
         let bar = self.get_or_create_bar(task_id, total).await;
+        bar.set_position(offset);
         bar.set_message("Downloading...");
         Ok(())
     }
 
-    async fn update_progress(&self, task_id: u32, progress: &DownloadProgress) -> Result<()> {
+    async fn update_progress(&self, task_id: TaskId, progress: &DownloadProgress) -> Result<()> {
         let bar = self.get_or_create_bar(task_id, progress.total_bytes).await;
         bar.set_position(progress.bytes_downloaded);
 
-        let speed = if progress.rate > 1_000_000.0 {
-            format!("{:.2} MB/s", progress.rate / 1_000_000.0)
-        } else if progress.rate > 1_000.0 {
-            format!("{:.2} KB/s", progress.rate / 1_000.0)
+        let rate = progress.current_rate;
+        let speed = if rate > 1_000_000.0 {
+            format!("{:.2} MB/s", rate / 1_000_000.0)
+        } else if rate > 1_000.0 {
+            format!("{:.2} KB/s", rate / 1_000.0)
         } else {
-            format!("{:.0} B/s", progress.rate)
+            format!("{:.0} B/s", rate)
         };
 
-        // 格式化剩余时间
-        let eta = if progress.remaining_time.as_secs() > 60 {
-            format!(
-                "{}m {}s",
-                progress.remaining_time.as_secs() / 60,
-                progress.remaining_time.as_secs() % 60
-            )
-        } else {
-            format!("{}s", progress.remaining_time.as_secs())
+        // 格式化剩余时间，总大小未知时无法估计
+        let eta = match progress.remaining_time {
+            Some(remaining) if remaining.as_secs() > 60 => {
+                format!(
+                    "{}m {}s",
+                    remaining.as_secs() / 60,
+                    remaining.as_secs() % 60
+                )
+            }
+            Some(remaining) => format!("{}s", remaining.as_secs()),
+            None => "--".to_string(),
         };
 
         bar.set_message(format!("Speed: {} | ETA: {}", speed, eta));
@@ -96,16 +118,28 @@ impl ProgressReporter for TuiReporter {
         Ok(())
     }
 
-    async fn finish_task(&self, task_id: u32, result: DownloadResult) -> Result<()> {
+    async fn finish_task(&self, task_id: TaskId, result: DownloadResult) -> Result<()> {
         let mut bars = self.bars.lock().await;
         if let Some(bar) = bars.remove(&task_id) {
+            // 旋转指示器没有总大小，结束时只显示已下载字节数
+            let has_total = bar.length().is_some();
+            let template = |spinner: &str, color: &str| {
+                if has_total {
+                    format!(
+                        "{{spinner:.{}}} [{{bar:.{}/blue}}] {{bytes}}/{{total_bytes}} ({}): {{msg}}",
+                        spinner, color, task_id
+                    )
+                } else {
+                    format!("{{spinner:.{}}} {{bytes}} ({}): {{msg}}", spinner, task_id)
+                }
+            };
             match result {
                 DownloadResult::Success { path, duration, .. } => {
                     // 条的颜色变成绿色，还是#>-
-                    bar.set_style(ProgressStyle::with_template(&format!(
-                        "{{spinner:.green}} [{{bar:.green/blue}}] {{bytes}}/{{total_bytes}} ({}): {{msg}}",
-                        task_id
-                    ))?.progress_chars("#>-"));
+                    bar.set_style(
+                        ProgressStyle::with_template(&template("green", "green"))?
+                            .progress_chars("#>-"),
+                    );
                     let success_message = format!(
                         "✅ Done in {}s, saved to {}",
                         duration.as_secs(),
@@ -115,19 +149,21 @@ impl ProgressReporter for TuiReporter {
                 }
                 DownloadResult::Failed { error, .. } => {
                     // 条变成红色
-                    bar.set_style(ProgressStyle::default_bar().template(&format!(
-                        "{{spinner:.red}} [{{bar:.red/blue}}] {{bytes}}/{{total_bytes}} ({}): {{msg}}",
-                        task_id
-                    ))?.progress_chars("#>-"));
+                    bar.set_style(
+                        ProgressStyle::default_bar()
+                            .template(&template("red", "red"))?
+                            .progress_chars("#>-"),
+                    );
                     let error_message = format!("❌ Error: {}", error);
                     bar.abandon_with_message(error_message)
                 }
                 DownloadResult::Canceled => {
                     // 条变成黄色
-                    bar.set_style(ProgressStyle::default_bar().template(&format!(
-                        "{{spinner:.red}} [{{bar:.yellow/blue}}] {{bytes}}/{{total_bytes}} ({}): {{msg}}",
-                        task_id
-                    ))?.progress_chars("#>-"));
+                    bar.set_style(
+                        ProgressStyle::default_bar()
+                            .template(&template("red", "yellow"))?
+                            .progress_chars("#>-"),
+                    );
                     bar.abandon_with_message("⛔ Canceled")
                 }
             }
@@ -141,7 +177,7 @@ impl ResultReporter for TuiReporter {
     async fn operation_result(
         &self,
         operation: OperationType,
-        task_id: u32,
+        task_id: TaskId,
         code: u32,
         message: String,
     ) -> Result<()> {
@@ -8,6 +8,12 @@ use async_trait::async_trait;
 #[derive(Debug, Clone)]
 pub struct UrlResolver {}
 
+impl Default for UrlResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl UrlResolver {
     pub fn new() -> Self {
         Self {}
@@ -23,6 +29,8 @@ impl ResourceResolver for UrlResolver {
                 url: url.clone(),
                 headers: vec![],
                 auth: None,
+                checksum: None,
+                priority: 0,
             }),
             DownloadResource::Resolved(resolved) => Ok(resolved.clone()),
             _ => Err("Unsupported resource type".into()),
@@ -0,0 +1,367 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore, watch};
+
+use crate::base::algorithms::url_host;
+use crate::base::enums::{DownloadResource, SchedulePolicy};
+use crate::base::structs::TaskId;
+use crate::error::Result;
+
+/// 基于信号量的并发调度器，维护等待下载的任务队列，每个下载中的任务占用一个名额
+///
+/// 名额数量可以在下载过程中随时调整：扩大时排队的任务立即开始，
+/// 缩小时正在下载的任务不受影响，它们结束后归还的名额会被收回，超出部分继续排队
+#[derive(Debug)]
+pub struct Scheduler {
+    semaphore: Arc<Semaphore>,
+    inner: Mutex<Inner>,
+    queue: Mutex<Queue>,
+    /// 有新任务入队时通知
+    queued: Notify,
+    /// 队列为空且没有进行中的任务
+    idle: watch::Sender<bool>,
+}
+
+/// 排队等待下载的任务
+#[derive(Debug, Clone)]
+pub struct QueuedTask {
+    pub id: TaskId,
+    pub resource: DownloadResource,
+    /// 优先级，数值越大越先开始
+    pub priority: i32,
+    /// 文件大小，用于小文件优先的策略
+    pub size: Option<u64>,
+    /// 资源所在的域名，用于按域名轮流的策略；未解析的资源为None
+    pub domain: Option<String>,
+    /// 入队顺序，其他条件相同时先入队的先开始
+    seq: u64,
+}
+
+impl QueuedTask {
+    pub fn new(id: TaskId, resource: DownloadResource, priority: i32) -> Self {
+        let domain = match &resource {
+            DownloadResource::Url(url) => Some(url.as_str()),
+            DownloadResource::Resolved(resolved) => Some(resolved.url.as_str()),
+            _ => None,
+        }
+        .and_then(url_host);
+
+        Self {
+            id,
+            resource,
+            priority,
+            size: None,
+            domain,
+            seq: 0,
+        }
+    }
+
+    pub fn with_size(mut self, size: Option<u64>) -> Self {
+        self.size = size;
+        self
+    }
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    entries: Vec<QueuedTask>,
+    next_seq: u64,
+    /// 排队中与下载中的任务总数
+    outstanding: usize,
+    policy: SchedulePolicy,
+    /// 各域名最近一次开始任务的轮次，按域名轮流时轮次最早的先开始
+    served: HashMap<Option<String>, u64>,
+    round: u64,
+}
+
+impl Queue {
+    /// 排序键，越小越先开始
+    fn rank(&self, entry: &QueuedTask) -> (u64, Reverse<i32>, u64) {
+        match self.policy {
+            SchedulePolicy::Fifo => (0, Reverse(0), entry.seq),
+            SchedulePolicy::Priority => (0, Reverse(entry.priority), entry.seq),
+            SchedulePolicy::SmallestFirst => (
+                entry.size.unwrap_or(u64::MAX),
+                Reverse(entry.priority),
+                entry.seq,
+            ),
+            SchedulePolicy::RoundRobin => (
+                self.served.get(&entry.domain).copied().unwrap_or(0),
+                Reverse(entry.priority),
+                entry.seq,
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    limit: usize,
+    /// 缩小时被占用、尚未收回的名额数
+    debt: usize,
+}
+
+/// 占用中的名额，drop 时归还（或在缩小后被收回）
+#[derive(Debug)]
+pub struct Slot {
+    permit: Option<OwnedSemaphorePermit>,
+    scheduler: Arc<Scheduler>,
+}
+
+impl Scheduler {
+    pub fn new(limit: usize) -> Self {
+        let limit = limit.max(1);
+        Self {
+            semaphore: Arc::new(Semaphore::new(limit)),
+            inner: Mutex::new(Inner { limit, debt: 0 }),
+            queue: Mutex::new(Queue::default()),
+            queued: Notify::new(),
+            idle: watch::channel(true).0,
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.inner.lock().unwrap().limit
+    }
+
+    /// 调整名额数量（至少为1）
+    pub fn set_limit(&self, limit: usize) {
+        let limit = limit.max(1);
+        let mut inner = self.inner.lock().unwrap();
+        if limit > inner.limit {
+            // 先抵消尚未收回的名额，剩余部分新增
+            let added = limit - inner.limit;
+            let repaid = added.min(inner.debt);
+            inner.debt -= repaid;
+            self.semaphore.add_permits(added - repaid);
+        } else {
+            // 空闲名额立即收回，被占用的名额等归还时再收回
+            let removed = inner.limit - limit;
+            let forgotten = self.semaphore.forget_permits(removed);
+            inner.debt += removed - forgotten;
+        }
+        inner.limit = limit;
+    }
+
+    /// 等待并获取一个名额，按请求顺序分配
+    pub async fn acquire(self: &Arc<Self>) -> Result<Slot> {
+        let permit = self
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(Slot {
+            permit: Some(permit),
+            scheduler: self.clone(),
+        })
+    }
+
+    pub fn set_policy(&self, policy: SchedulePolicy) {
+        self.queue.lock().unwrap().policy = policy;
+    }
+
+    /// 将一批任务加入队列，同一批的任务一起参与排序
+    pub fn push(&self, entries: impl IntoIterator<Item = QueuedTask>) {
+        {
+            let mut queue = self.queue.lock().unwrap();
+            for mut entry in entries {
+                entry.seq = queue.next_seq;
+                queue.next_seq += 1;
+                queue.outstanding += 1;
+                queue.entries.push(entry);
+            }
+            if queue.outstanding > 0 {
+                self.idle.send_replace(false);
+            }
+        }
+        self.queued.notify_one();
+    }
+
+    /// 按调度策略取出下一个要开始的任务
+    pub fn pop(&self) -> Option<QueuedTask> {
+        let mut queue = self.queue.lock().unwrap();
+        let index = queue
+            .entries
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, entry)| queue.rank(entry))
+            .map(|(index, _)| index)?;
+        let entry = queue.entries.remove(index);
+
+        queue.round += 1;
+        let round = queue.round;
+        queue.served.insert(entry.domain.clone(), round);
+        Some(entry)
+    }
+
+    /// 等待直到队列中有任务，任务仍留在队列中
+    ///
+    /// 分派时先等待任务再获取名额，队列为空时不占用名额
+    pub async fn wait_queued(&self) {
+        loop {
+            let notified = self.queued.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if !self.queue.lock().unwrap().entries.is_empty() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// 从队列中移除尚未开始的任务，返回是否找到
+    pub fn remove(&self, id: TaskId) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        let len = queue.entries.len();
+        queue.entries.retain(|entry| entry.id != id);
+        let removed = len - queue.entries.len();
+        queue.outstanding -= removed;
+        removed > 0
+    }
+
+    /// 清空队列中尚未开始的任务，返回它们的ID
+    pub fn clear(&self) -> Vec<TaskId> {
+        let mut queue = self.queue.lock().unwrap();
+        let entries = std::mem::take(&mut queue.entries);
+        queue.outstanding -= entries.len();
+        entries.into_iter().map(|entry| entry.id).collect()
+    }
+
+    /// 调整排队中任务的优先级，返回是否找到
+    pub fn reprioritize(&self, id: TaskId, priority: i32) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        let mut found = false;
+        for entry in queue.entries.iter_mut().filter(|entry| entry.id == id) {
+            entry.priority = priority;
+            found = true;
+        }
+        found
+    }
+
+    /// 排队中的任务数
+    pub fn queued_len(&self) -> usize {
+        self.queue.lock().unwrap().entries.len()
+    }
+
+    /// 一个已开始的任务结束，返回此后是否已没有任何任务
+    pub fn finish(&self) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        queue.outstanding -= 1;
+        queue.outstanding == 0
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.queue.lock().unwrap().outstanding == 0
+    }
+
+    /// 仍然没有任务时通知 wait_idle 的等待者
+    pub fn publish_idle(&self) {
+        let queue = self.queue.lock().unwrap();
+        if queue.outstanding == 0 {
+            self.idle.send_replace(true);
+        }
+    }
+
+    /// 等待队列为空且没有进行中的任务
+    pub async fn wait_idle(&self) {
+        let mut idle = self.idle.subscribe();
+        idle.wait_for(|idle| *idle).await.ok();
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let Some(permit) = self.permit.take() else {
+            return;
+        };
+        let mut inner = self.scheduler.inner.lock().unwrap();
+        if inner.debt > 0 {
+            inner.debt -= 1;
+            permit.forget();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scheduler_resize() {
+        let scheduler = Arc::new(Scheduler::new(2));
+        let first = scheduler.acquire().await.unwrap();
+        let second = scheduler.acquire().await.unwrap();
+        assert_eq!(scheduler.semaphore.available_permits(), 0);
+
+        // 缩小时不影响已占用的名额，归还后才收回
+        scheduler.set_limit(1);
+        drop(first);
+        assert_eq!(scheduler.semaphore.available_permits(), 0);
+        drop(second);
+        assert_eq!(scheduler.semaphore.available_permits(), 1);
+
+        let third = scheduler.acquire().await.unwrap();
+        scheduler.set_limit(3);
+        assert_eq!(scheduler.semaphore.available_permits(), 2);
+        assert_eq!(scheduler.limit(), 3);
+        drop(third);
+        assert_eq!(scheduler.semaphore.available_permits(), 3);
+    }
+
+    #[test]
+    fn test_queue_order() {
+        let scheduler = Scheduler::new(1);
+        let url = |s: &str| DownloadResource::Url(s.to_string());
+        scheduler.push([
+            QueuedTask::new(TaskId::new(1), url("a"), 0),
+            QueuedTask::new(TaskId::new(2), url("b"), 0),
+            QueuedTask::new(TaskId::new(3), url("c"), 0),
+            QueuedTask::new(TaskId::new(4), url("d"), 0),
+        ]);
+
+        assert!(scheduler.reprioritize(TaskId::new(3), 5));
+        assert!(scheduler.remove(TaskId::new(2)));
+        assert!(!scheduler.remove(TaskId::new(2)));
+
+        let order: Vec<u64> = std::iter::from_fn(|| scheduler.pop())
+            .map(|entry| entry.id.get())
+            .collect();
+        assert_eq!(order, vec![3, 1, 4]);
+
+        assert!(!scheduler.is_idle());
+        scheduler.finish();
+        scheduler.finish();
+        assert!(scheduler.finish());
+    }
+
+    #[test]
+    fn test_queue_policies() {
+        let entry = |id, host: &str, size| {
+            let resource = DownloadResource::Url(format!("http://{}/{}", host, id));
+            QueuedTask::new(TaskId::new(id), resource, 0).with_size(size)
+        };
+        let entries = || {
+            [
+                entry(1, "a.com", Some(300)),
+                entry(2, "a.com", None),
+                entry(3, "a.com", Some(100)),
+                entry(4, "b.com", Some(200)),
+            ]
+        };
+        let order = |policy| {
+            let scheduler = Scheduler::new(1);
+            scheduler.set_policy(policy);
+            scheduler.push(entries());
+            std::iter::from_fn(|| scheduler.pop())
+                .map(|entry| entry.id.get())
+                .collect::<Vec<u64>>()
+        };
+
+        assert_eq!(order(SchedulePolicy::Fifo), vec![1, 2, 3, 4]);
+        assert_eq!(order(SchedulePolicy::SmallestFirst), vec![3, 4, 1, 2]);
+        assert_eq!(order(SchedulePolicy::RoundRobin), vec![1, 4, 2, 3]);
+    }
+}
@@ -0,0 +1,138 @@
+use super::memory::upsert;
+use crate::base::traits::StateStore;
+use crate::error::Result;
+use crate::journal::StateJournal;
+use crate::task::{PersistentState, TaskStateRecord};
+use async_trait::async_trait;
+use std::path::PathBuf;
+
+/// 保存在JSON文件中的状态，每次写入都会原子地替换整个文件
+#[derive(Debug)]
+pub struct JsonStateStore {
+    path: PathBuf,
+    /// 读取-修改-写入期间持有，避免并发写入互相覆盖
+    lock: tokio::sync::Mutex<()>,
+}
+
+impl JsonStateStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// 使用目录下的 downloading.json，与 DownloadOptions::save_path 配合使用
+    pub fn in_dir(save_path: &str) -> Self {
+        Self::new(StateJournal::path(save_path))
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    async fn modify(&self, f: impl FnOnce(&mut Vec<TaskStateRecord>)) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut records = StateJournal::load(&self.path)
+            .await?
+            .map(|state| state.tasks)
+            .unwrap_or_default();
+        f(&mut records);
+        StateJournal::write(&self.path, &PersistentState::new(records)).await
+    }
+}
+
+#[async_trait]
+impl StateStore for JsonStateStore {
+    async fn load(&self) -> Result<Option<PersistentState>> {
+        StateJournal::load(&self.path).await
+    }
+
+    async fn upsert_task(&self, record: &TaskStateRecord) -> Result<()> {
+        self.modify(|records| upsert(records, record.clone())).await
+    }
+
+    async fn remove_task(&self, key: &str) -> Result<()> {
+        self.modify(|records| records.retain(|record| record.content_key() != key))
+            .await
+    }
+
+    async fn list(&self) -> Result<Vec<TaskStateRecord>> {
+        Ok(self
+            .load()
+            .await?
+            .map(|state| state.tasks)
+            .unwrap_or_default())
+    }
+
+    async fn upsert_all(&self, records: &[TaskStateRecord]) -> Result<()> {
+        self.modify(|existing| {
+            for record in records {
+                upsert(existing, record.clone());
+            }
+        })
+        .await
+    }
+
+    /// 删除状态文件
+    async fn clear(&self) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        StateJournal::remove(&self.path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::algorithms::content_key;
+    use crate::task::STATE_VERSION;
+
+    #[tokio::test]
+    async fn test_json_store() {
+        let dir = std::env::temp_dir().join(format!("vielpork-json-store-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let store = JsonStateStore::in_dir(&dir.to_string_lossy());
+
+        // 状态文件不存在时视为没有状态
+        assert!(store.load().await.unwrap().is_none());
+        assert!(store.list().await.unwrap().is_empty());
+
+        let record = TaskStateRecord::from_value(
+            serde_json::json!({
+                "id": 1, "url": "http://a/1", "key": "k1", "downloaded_bytes": 64,
+                "total_bytes": 128, "file_path": "a.bin", "state": "Paused",
+                "etag": "\"v1\"", "resource_id": 3, "headers": [["X-Test", "one"]],
+                "auth": {"Basic": {"username": "user"}}, "checksum": {"MD5": "abc"},
+                "segments": [{"start": 0, "end": 64, "written": 64}],
+            }),
+            STATE_VERSION,
+        )
+        .unwrap();
+        store.upsert_task(&record).await.unwrap();
+        let state = store.load().await.unwrap().unwrap();
+        assert_eq!(state.version, STATE_VERSION);
+        assert_eq!(
+            serde_json::to_value(&state.tasks).unwrap(),
+            serde_json::to_value([&record]).unwrap()
+        );
+
+        // 版本1的状态文件载入时迁移，下次写入时以当前版本保存
+        let legacy = r#"{"tasks":[{"id":2,"url":"http://a/2","downloaded_bytes":5,
+            "total_bytes":10,"file_path":"b.bin","state":"Paused"}]}"#;
+        tokio::fs::write(store.path(), legacy).await.unwrap();
+        let state = store.load().await.unwrap().unwrap();
+        assert_eq!(state.version, STATE_VERSION);
+        assert_eq!(state.tasks[0].key, content_key("http://a/2"));
+        store.upsert_task(&record).await.unwrap();
+        let contents = tokio::fs::read_to_string(store.path()).await.unwrap();
+        let saved: serde_json::Value = serde_json::from_str(&contents).unwrap();
+        assert_eq!(saved["version"], STATE_VERSION);
+        assert_eq!(saved["tasks"][0]["key"], content_key("http://a/2"));
+        assert_eq!(store.list().await.unwrap().len(), 2);
+
+        store.clear().await.unwrap();
+        assert!(!store.path().exists());
+        assert!(store.load().await.unwrap().is_none());
+        tokio::fs::remove_dir_all(&dir).await.ok();
+    }
+}
@@ -0,0 +1,111 @@
+use crate::base::traits::StateStore;
+use crate::error::Result;
+use crate::task::{PersistentState, TaskStateRecord};
+use async_trait::async_trait;
+use std::sync::Mutex;
+
+/// 只保存在内存中的状态，进程退出后丢失
+#[derive(Debug, Default)]
+pub struct MemoryStateStore {
+    /// 从未写入过时为None，与状态文件不存在对应
+    records: Mutex<Option<Vec<TaskStateRecord>>>,
+}
+
+impl MemoryStateStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl StateStore for MemoryStateStore {
+    async fn load(&self) -> Result<Option<PersistentState>> {
+        let records = self.records.lock().unwrap();
+        Ok(records.clone().map(PersistentState::new))
+    }
+
+    async fn upsert_task(&self, record: &TaskStateRecord) -> Result<()> {
+        let mut records = self.records.lock().unwrap();
+        upsert(records.get_or_insert_default(), record.clone());
+        Ok(())
+    }
+
+    async fn remove_task(&self, key: &str) -> Result<()> {
+        if let Some(records) = self.records.lock().unwrap().as_mut() {
+            records.retain(|record| record.content_key() != key);
+        }
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<TaskStateRecord>> {
+        Ok(self.records.lock().unwrap().clone().unwrap_or_default())
+    }
+
+    async fn clear(&self) -> Result<()> {
+        *self.records.lock().unwrap() = None;
+        Ok(())
+    }
+}
+
+/// 替换同一资源的记录，没有时追加到末尾
+pub(crate) fn upsert(records: &mut Vec<TaskStateRecord>, record: TaskStateRecord) {
+    let key = record.content_key();
+    match records.iter_mut().find(|r| r.content_key() == key) {
+        Some(existing) => *existing = record,
+        None => records.push(record),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::STATE_VERSION;
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        let store = MemoryStateStore::new();
+        assert!(store.load().await.unwrap().is_none());
+
+        let record = TaskStateRecord::from_value(
+            serde_json::json!({
+                "id": 1, "url": "http://a/1", "key": "k1", "downloaded_bytes": 64,
+                "total_bytes": 128, "file_path": "a.bin", "state": "Paused",
+                "etag": "\"v1\"", "resource_id": 3, "headers": [["X-Test", "one"]],
+                "auth": {"Basic": {"username": "user"}}, "checksum": {"MD5": "abc"},
+                "segments": [{"start": 0, "end": 64, "written": 64}],
+            }),
+            STATE_VERSION,
+        )
+        .unwrap();
+        store.upsert_task(&record).await.unwrap();
+        let state = store.load().await.unwrap().unwrap();
+        assert_eq!(state.version, STATE_VERSION);
+        assert_eq!(
+            serde_json::to_value(&state.tasks).unwrap(),
+            serde_json::to_value([&record]).unwrap()
+        );
+
+        // 版本1的记录迁移后补充内容键
+        let legacy = TaskStateRecord::from_value(
+            serde_json::json!({
+                "id": 2, "url": "http://a/2", "downloaded_bytes": 0,
+                "total_bytes": null, "file_path": "b.bin", "state": "Paused",
+            }),
+            1,
+        )
+        .unwrap();
+        store.upsert_task(&legacy).await.unwrap();
+        let state = store.load().await.unwrap().unwrap();
+        assert_eq!(state.version, STATE_VERSION);
+        assert_eq!(state.tasks.len(), 2);
+        assert_eq!(
+            state.tasks[1].key,
+            crate::base::algorithms::content_key("http://a/2")
+        );
+
+        store.remove_task("k1").await.unwrap();
+        assert_eq!(store.list().await.unwrap().len(), 1);
+        store.clear().await.unwrap();
+        assert!(store.load().await.unwrap().is_none());
+    }
+}
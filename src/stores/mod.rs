@@ -0,0 +1,6 @@
+/// 状态保存在 `<save_path>/downloading.json` 中，默认方式
+pub mod json;
+/// 状态只保存在内存中，用于测试
+pub mod memory;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
@@ -0,0 +1,195 @@
+use crate::base::traits::StateStore;
+use crate::error::Result;
+use crate::task::{PersistentState, STATE_VERSION, TaskStateRecord};
+use async_trait::async_trait;
+use rusqlite::{Connection, OptionalExtension, params};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// 记录所在的表名
+pub const TABLE: &str = "vielpork_tasks";
+
+/// 保存在SQLite数据库中的状态，每个资源一行，记录本身以JSON保存
+///
+/// 可以使用应用已有的数据库连接，表不存在时自动创建
+#[derive(Debug, Clone)]
+pub struct SqliteStateStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStateStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    pub fn from_connection(conn: Connection) -> Result<Self> {
+        conn.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {TABLE} (
+                key TEXT PRIMARY KEY,
+                url TEXT NOT NULL,
+                state TEXT NOT NULL,
+                version INTEGER NOT NULL,
+                record TEXT NOT NULL,
+                updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )"
+        ))?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// 在阻塞线程池中使用连接
+    async fn with_conn<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || f(&mut conn.lock().unwrap()))
+            .await
+            .map_err(|e| e.to_string())?
+    }
+}
+
+fn upsert(conn: &Connection, record: &TaskStateRecord) -> Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO {TABLE} (key, url, state, version, record, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, CURRENT_TIMESTAMP)
+             ON CONFLICT(key) DO UPDATE SET url = ?2, state = ?3, version = ?4, record = ?5,
+                 updated_at = CURRENT_TIMESTAMP"
+        ),
+        params![
+            record.content_key(),
+            record.url,
+            format!("{:?}", record.state),
+            STATE_VERSION,
+            serde_json::to_string(record)?,
+        ],
+    )?;
+    Ok(())
+}
+
+fn list(conn: &Connection) -> Result<Vec<TaskStateRecord>> {
+    let mut statement = conn.prepare(&format!(
+        "SELECT version, record FROM {TABLE} ORDER BY rowid"
+    ))?;
+    let rows = statement.query_map([], |row| {
+        Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?))
+    })?;
+    let mut records = Vec::new();
+    for row in rows {
+        let (version, record) = row?;
+        // 旧版本写入的记录在读取时迁移
+        records.push(TaskStateRecord::from_value(
+            serde_json::from_str(&record)?,
+            version,
+        )?);
+    }
+    Ok(records)
+}
+
+#[async_trait]
+impl StateStore for SqliteStateStore {
+    async fn load(&self) -> Result<Option<PersistentState>> {
+        self.with_conn(|conn| {
+            let any: Option<i64> = conn
+                .query_row(&format!("SELECT 1 FROM {TABLE} LIMIT 1"), [], |row| {
+                    row.get(0)
+                })
+                .optional()?;
+            match any {
+                Some(_) => Ok(Some(PersistentState::new(list(conn)?))),
+                None => Ok(None),
+            }
+        })
+        .await
+    }
+
+    async fn upsert_task(&self, record: &TaskStateRecord) -> Result<()> {
+        let record = record.clone();
+        self.with_conn(move |conn| upsert(conn, &record)).await
+    }
+
+    async fn remove_task(&self, key: &str) -> Result<()> {
+        let key = key.to_string();
+        self.with_conn(move |conn| {
+            conn.execute(&format!("DELETE FROM {TABLE} WHERE key = ?1"), [key])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list(&self) -> Result<Vec<TaskStateRecord>> {
+        self.with_conn(|conn| list(conn)).await
+    }
+
+    /// 在一个事务中写入所有记录
+    async fn upsert_all(&self, records: &[TaskStateRecord]) -> Result<()> {
+        let records = records.to_vec();
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            for record in &records {
+                upsert(&tx, record)?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.with_conn(|conn| {
+            conn.execute(&format!("DELETE FROM {TABLE}"), [])?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::enums::TaskState;
+    use crate::base::structs::TaskId;
+
+    #[tokio::test]
+    async fn test_sqlite_store() {
+        let store = SqliteStateStore::open_in_memory().unwrap();
+        assert!(store.load().await.unwrap().is_none());
+
+        let record = |url: &str, state| {
+            TaskStateRecord::from_value(
+                serde_json::json!({
+                    "id": TaskId::new(1), "url": url, "downloaded_bytes": 0,
+                    "total_bytes": null, "file_path": "a.bin", "state": state,
+                }),
+                1,
+            )
+            .unwrap()
+        };
+        store
+            .upsert_all(&[
+                record("http://a/1", TaskState::Paused),
+                record("http://a/2", TaskState::Paused),
+            ])
+            .await
+            .unwrap();
+        store
+            .upsert_task(&record("http://a/1", TaskState::Completed))
+            .await
+            .unwrap();
+
+        let records = store.list().await.unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].state, TaskState::Completed);
+
+        store.remove_task(&records[1].key).await.unwrap();
+        assert_eq!(store.load().await.unwrap().unwrap().tasks.len(), 1);
+        store.clear().await.unwrap();
+        assert!(store.load().await.unwrap().is_none());
+    }
+}
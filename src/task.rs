@@ -1,85 +1,385 @@
 use serde::{Deserialize, Serialize};
+use std::future::Future;
 use std::path::PathBuf;
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::sync::{Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll};
+use tokio::sync::{Mutex, Notify, RwLock, oneshot, watch};
 use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 
-use crate::base::enums::TaskState;
-use crate::base::structs::DownloadProgress;
-use crate::error::Result;
+use crate::base::algorithms::content_key;
+use crate::base::enums::{DownloadResult, FileChecksum, TaskState};
+use crate::base::structs::{
+    AuthRef, DownloadProgress, ResolvedResource, Segment, SegmentProgress, TaskId,
+};
+use crate::downloader::Downloader;
+use crate::error::{ErrorKind, Result};
 
-#[derive(Serialize, Deserialize)]
+/// 状态文件的当前版本
+///
+/// 1: 没有 version 字段的旧格式；2: 增加内容键、请求头、认证方式、校验值与分段进度
+pub const STATE_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PersistentState {
+    /// 状态文件格式的版本，旧版本在载入时迁移
+    #[serde(default = "legacy_version")]
+    pub version: u32,
     pub tasks: Vec<TaskStateRecord>,
 }
 
-#[derive(Serialize, Deserialize)]
+fn legacy_version() -> u32 {
+    1
+}
+
+impl PersistentState {
+    pub fn new(tasks: Vec<TaskStateRecord>) -> Self {
+        Self {
+            version: STATE_VERSION,
+            tasks,
+        }
+    }
+
+    /// 解析状态文件，旧版本的格式会被迁移到当前版本
+    pub fn from_json(contents: &str) -> Result<Self> {
+        let mut value: serde_json::Value = serde_json::from_str(contents)?;
+        let version = value
+            .get("version")
+            .and_then(|version| version.as_u64())
+            .map_or(legacy_version(), |version| version as u32);
+        if version > STATE_VERSION {
+            return Err(ErrorKind::UnsupportedStateVersion(version).into());
+        }
+
+        let tasks: Vec<serde_json::Value> = serde_json::from_value(
+            value
+                .get_mut("tasks")
+                .map(serde_json::Value::take)
+                .unwrap_or_default(),
+        )?;
+        let tasks = tasks
+            .into_iter()
+            .map(|task| TaskStateRecord::from_value(task, version))
+            .collect::<Result<_>>()?;
+        Ok(Self::new(tasks))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TaskStateRecord {
-    pub id: u32,
+    pub id: TaskId,
     pub url: String,
+    /// 规范化URL的哈希，跨会话识别同一资源
+    #[serde(default)]
+    pub key: String,
     pub downloaded_bytes: u64,
-    pub total_bytes: u64,
+    pub total_bytes: Option<u64>,
     pub file_path: PathBuf,
     pub state: TaskState,
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub last_modified: Option<String>,
+    /// 解析器给出的资源ID
+    #[serde(default)]
+    pub resource_id: u32,
+    /// 解析后的请求头，续传时无需重新解析
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    /// 认证方式（不含凭据），不为None时续传需要重新解析以取得凭据
+    #[serde(default)]
+    pub auth: Option<AuthRef>,
+    #[serde(default)]
+    pub checksum: Option<FileChecksum>,
+    /// 分段下载中各分段的进度，不分段或分段已合并时为空
+    #[serde(default)]
+    pub segments: Vec<SegmentProgress>,
+}
+
+impl TaskStateRecord {
+    /// 解析指定版本格式的单条记录，旧版本的格式会被迁移到当前版本
+    pub fn from_value(mut value: serde_json::Value, version: u32) -> Result<Self> {
+        if version > STATE_VERSION {
+            return Err(ErrorKind::UnsupportedStateVersion(version).into());
+        }
+        if version < 2 {
+            // 1 -> 2：补充内容键，其余新增字段使用默认值
+            let key = value
+                .get("url")
+                .and_then(|url| url.as_str())
+                .map(content_key);
+            if let (Some(task), Some(key)) = (value.as_object_mut(), key) {
+                task.entry("key").or_insert(key.into());
+            }
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// 内容键，旧版本的状态文件没有记录时根据URL计算
+    pub fn content_key(&self) -> String {
+        if self.key.is_empty() {
+            content_key(&self.url)
+        } else {
+            self.key.clone()
+        }
+    }
+
+    /// 根据记录还原解析后的资源；记录了认证方式时不含凭据，需要重新解析
+    pub fn to_resolved(&self) -> ResolvedResource {
+        ResolvedResource {
+            id: self.resource_id,
+            url: self.url.clone(),
+            headers: self.headers.clone(),
+            auth: None,
+            checksum: self.checksum.clone(),
+            priority: 0,
+        }
+    }
+}
+
+/// 加入队列的任务的句柄
+///
+/// 可以查看进度、暂停/恢复/取消该任务，await 句柄得到下载结果。
+/// 任务未开始就被移出队列（如关闭下载器）时结果为 Canceled
+pub struct TaskHandle {
+    id: TaskId,
+    downloader: Downloader,
+    progress: watch::Receiver<DownloadProgress>,
+    result: oneshot::Receiver<DownloadResult>,
+}
+
+impl TaskHandle {
+    /// 创建句柄及下载器一侧用于发送进度与结果的一端
+    pub(crate) fn new(id: TaskId, downloader: Downloader) -> (Self, HandleSender) {
+        let (progress_tx, progress) = watch::channel(DownloadProgress::new(None));
+        let (result_tx, result) = oneshot::channel();
+        let handle = Self {
+            id,
+            downloader,
+            progress,
+            result,
+        };
+        let sender = HandleSender {
+            progress: progress_tx,
+            result: result_tx,
+        };
+        (handle, sender)
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// 任务进度，每写入一块数据更新一次
+    pub fn progress(&self) -> watch::Receiver<DownloadProgress> {
+        self.progress.clone()
+    }
+
+    pub async fn pause(&self) -> Result<()> {
+        self.downloader.pause_task(self.id).await
+    }
+
+    pub async fn resume(&self) -> Result<()> {
+        self.downloader.resume_task(self.id).await
+    }
+
+    /// 取消任务，排队中的任务直接出队；返回时任务已释放文件与连接
+    pub async fn cancel(&self) -> Result<()> {
+        self.downloader.cancel_task(self.id).await
+    }
+}
+
+impl std::fmt::Debug for TaskHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TaskHandle").field("id", &self.id).finish()
+    }
+}
+
+impl Future for TaskHandle {
+    type Output = DownloadResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // 下载器丢弃发送端而没有结果时，任务已不会再执行
+        Pin::new(&mut self.result)
+            .poll(cx)
+            .map(|result| result.unwrap_or(DownloadResult::Canceled))
+    }
+}
+
+/// 下载器持有的句柄另一端
+#[derive(Debug)]
+pub(crate) struct HandleSender {
+    progress: watch::Sender<DownloadProgress>,
+    result: oneshot::Sender<DownloadResult>,
+}
+
+impl HandleSender {
+    pub(crate) fn progress(&self, progress: &DownloadProgress) {
+        self.progress.send_replace(progress.clone());
+    }
+
+    /// 发送结果，句柄已被丢弃时忽略
+    pub(crate) fn finish(self, result: DownloadResult) {
+        self.result.send(result).ok();
+    }
+}
+
+/// 执行一次下载的tokio任务及其取消令牌
+///
+/// 在下载开始前创建，下载器与 DownloadTask 共享，取消后可以等待任务真正结束
+#[derive(Debug, Clone)]
+pub(crate) struct TaskControl {
+    pub(crate) cancel_token: CancellationToken,
+    handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl TaskControl {
+    pub(crate) fn new(cancel_token: CancellationToken) -> Self {
+        Self {
+            cancel_token,
+            handle: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// 启动执行下载的tokio任务；记录句柄之前持有锁，join 一定能等到它
+    pub(crate) async fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut handle = self.handle.lock().await;
+        *handle = Some(tokio::spawn(future));
+    }
+
+    /// 等待执行下载的tokio任务结束，任务未启动时立即返回
+    pub(crate) async fn join(&self) {
+        let mut handle = self.handle.lock().await;
+        if let Some(handle) = handle.take() {
+            handle.await.ok();
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct DownloadTask {
-    pub id: u32,
+    pub id: TaskId,
     pub url: String,
-    handle: Arc<Mutex<Option<JoinHandle<()>>>>,
-    cancel_token: tokio_util::sync::CancellationToken,
+    /// 规范化URL的哈希，用于去重
+    pub key: String,
+    control: TaskControl,
     pub state: Arc<RwLock<TaskState>>,
+    /// 任务状态变化时通知，暂停中的任务在此等待
+    notifier: Arc<Notify>,
+    /// 任务因下载器挂起而暂停，挂起结束时恢复；单独暂停或恢复任务时清除
+    suspended: Arc<AtomicBool>,
     pub progress: Arc<Mutex<DownloadProgress>>,
     pub file_path: PathBuf,
-    pub total_size: u64,
+    pub total_size: Option<u64>,
+    /// 开始下载时远程文件的ETag，续传时用于校验文件是否变化
+    pub etag: Option<String>,
+    /// 开始下载时远程文件的Last-Modified
+    pub last_modified: Option<String>,
+    /// 解析后的资源，保存状态时记录其请求头与认证方式
+    pub resolved: Option<ResolvedResource>,
+    /// 进行中的分段及其进度
+    segments: Arc<std::sync::Mutex<Vec<SegmentProgress>>>,
 }
 
 impl DownloadTask {
-    pub fn new(id: u32, url: String, file_path: PathBuf, total_size: u64) -> Self {
+    pub fn new(id: TaskId, url: String, file_path: PathBuf, total_size: Option<u64>) -> Self {
         Self {
             id,
+            key: content_key(&url),
             url,
-            handle: Arc::new(Mutex::new(None)),
-            cancel_token: tokio_util::sync::CancellationToken::new(),
+            control: TaskControl::new(CancellationToken::new()),
             state: Arc::new(RwLock::new(TaskState::default())),
-            progress: Arc::new(Mutex::new(DownloadProgress {
-                bytes_downloaded: 0,
-                total_bytes: total_size,
-                progress_percentage: 0.0,
-                rate: 0.0,
-                remaining_time: std::time::Duration::from_secs(0),
-            })),
+            notifier: Arc::new(Notify::new()),
+            suspended: Arc::new(AtomicBool::new(false)),
+            progress: Arc::new(Mutex::new(DownloadProgress::new(total_size))),
             file_path,
             total_size,
+            etag: None,
+            last_modified: None,
+            resolved: None,
+            segments: Arc::new(std::sync::Mutex::new(Vec::new())),
         }
     }
+
+    /// 使用下载器为这次下载创建的取消令牌与任务句柄
+    pub(crate) fn with_control(mut self, control: TaskControl) -> Self {
+        self.control = control;
+        self
+    }
+
+    pub fn with_resolved(mut self, resolved: ResolvedResource) -> Self {
+        self.resolved = Some(resolved);
+        self
+    }
+
+    /// 开始一轮分段下载，传入空列表表示分段已结束
+    pub fn set_segments(&self, segments: &[Segment]) {
+        *self.segments.lock().unwrap() = segments
+            .iter()
+            .map(|segment| SegmentProgress {
+                start: segment.start,
+                end: segment.end,
+                written: 0,
+            })
+            .collect();
+    }
+
+    /// 更新分段已写入的字节数
+    pub fn record_segment(&self, segment: &Segment, written: u64) {
+        let mut segments = self.segments.lock().unwrap();
+        if let Some(progress) = segments.iter_mut().find(|s| s.start == segment.start) {
+            progress.written = written;
+        }
+    }
+
+    pub fn segments(&self) -> Vec<SegmentProgress> {
+        self.segments.lock().unwrap().clone()
+    }
+
+    pub fn with_validator(mut self, etag: Option<String>, last_modified: Option<String>) -> Self {
+        self.etag = etag;
+        self.last_modified = last_modified;
+        self
+    }
     pub async fn transition_state(&self, new_state: TaskState) -> Result<()> {
         let mut current = self.state.write().await;
+        self.transition_locked(&mut current, new_state)
+    }
 
+    fn transition_locked(&self, current: &mut TaskState, new_state: TaskState) -> Result<()> {
         if *current == TaskState::Canceled {
             return Ok(());
         }
 
-        let valid = match (*current, new_state) {
-            (TaskState::Paused, TaskState::Downloading) => true,
-            (TaskState::Paused, TaskState::Paused) => true,
-            (TaskState::Paused, TaskState::Pending) => true,
-            (TaskState::Pending, TaskState::Paused) => true,
-            (TaskState::Pending, TaskState::Downloading) => true,
-            (TaskState::Downloading, TaskState::Paused) => true,
-            (TaskState::Downloading, TaskState::Completed) => true,
-            (TaskState::Failed, _) => true,
-            (_, TaskState::Failed) => true,
-            (_, TaskState::Canceled) => true,
-            _ => false,
-        };
+        let valid = *current == new_state
+            || matches!(
+                (*current, new_state),
+                (TaskState::Paused, TaskState::Downloading)
+                    | (TaskState::Paused, TaskState::Pending)
+                    | (TaskState::Pending, TaskState::Paused)
+                    | (TaskState::Pending, TaskState::Downloading)
+                    | (TaskState::Downloading, TaskState::Paused)
+                    | (TaskState::Downloading, TaskState::Completed)
+                    | (TaskState::Failed, _)
+                    | (_, TaskState::Failed)
+                    | (_, TaskState::Canceled)
+            );
 
         if valid {
             *current = new_state;
+            self.notifier.notify_waiters();
             Ok(())
         } else {
-            Err(format!("Cannot transition from {:?} to {:?}", *current, new_state).into())
+            Err(ErrorKind::InvalidStateTransition {
+                from: format!("{:?}", *current),
+                to: format!("{:?}", new_state),
+            }
+            .into())
         }
     }
 
@@ -88,21 +388,95 @@ impl DownloadTask {
     }
 
     pub async fn pause(&self) -> Result<()> {
-        self.transition_state(TaskState::Paused).await
+        let mut current = self.state.write().await;
+        self.suspended.store(false, Ordering::SeqCst);
+        self.transition_locked(&mut current, TaskState::Paused)
     }
 
     pub async fn resume(&self) -> Result<()> {
-        self.transition_state(TaskState::Pending).await
+        let mut current = self.state.write().await;
+        self.suspended.store(false, Ordering::SeqCst);
+        self.transition_locked(&mut current, TaskState::Pending)
     }
 
-    pub async fn cancel(&self) -> Result<()> {
-        self.cancel_token.cancel();
-        if let Some(handle) = self.handle.lock().await.take() {
-            handle.abort();
+    /// 下载器挂起时暂停任务，已被单独暂停的任务不受影响
+    pub(crate) async fn suspend(&self) -> Result<()> {
+        let mut current = self.state.write().await;
+        if *current == TaskState::Paused {
+            return Ok(());
         }
+        self.transition_locked(&mut current, TaskState::Paused)?;
+        self.suspended
+            .store(*current == TaskState::Paused, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// 下载器结束挂起，只恢复因挂起而暂停的任务
+    pub(crate) async fn end_suspend(&self) -> Result<()> {
+        let mut current = self.state.write().await;
+        if self.suspended.swap(false, Ordering::SeqCst) && *current == TaskState::Paused {
+            self.transition_locked(&mut current, TaskState::Pending)?;
+        }
+        Ok(())
+    }
+
+    /// 请求取消任务，进行中的网络操作会尽快放弃；使用 join 等待任务结束
+    pub async fn cancel(&self) -> Result<()> {
+        self.control.cancel_token.cancel();
         self.transition_state(TaskState::Canceled).await
     }
-    pub fn task_id(&self) -> u32 {
+
+    /// 等待执行下载的tokio任务结束，此时文件与连接都已释放
+    pub async fn join(&self) {
+        self.control.join().await;
+    }
+
+    pub(crate) fn cancel_token(&self) -> &CancellationToken {
+        &self.control.cancel_token
+    }
+
+    /// 等待任务离开暂停状态（恢复或取消），返回新的状态
+    pub async fn wait_while_paused(&self) -> TaskState {
+        loop {
+            // 先注册通知再检查状态，避免错过两者之间发生的状态变化
+            let notified = self.notifier.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            let state = *self.state.read().await;
+            if state != TaskState::Paused {
+                return state;
+            }
+            notified.await;
+        }
+    }
+
+    pub fn task_id(&self) -> TaskId {
         self.id
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_migration() {
+        let legacy = r#"{"tasks":[{"id":7,"url":"http://example.com/a.bin","downloaded_bytes":5,
+            "total_bytes":10,"file_path":"a.bin","state":"Paused"}]}"#;
+        let state = PersistentState::from_json(legacy).unwrap();
+        assert_eq!(state.version, STATE_VERSION);
+        assert_eq!(state.tasks[0].key, content_key("http://example.com/a.bin"));
+        assert!(state.tasks[0].segments.is_empty());
+
+        let current = serde_json::to_string(&state).unwrap();
+        let reloaded = PersistentState::from_json(&current).unwrap();
+        assert_eq!(reloaded.tasks[0].key, state.tasks[0].key);
+
+        let future = r#"{"version":99,"tasks":[]}"#;
+        assert!(matches!(
+            PersistentState::from_json(future).unwrap_err().kind(),
+            ErrorKind::UnsupportedStateVersion(99)
+        ));
+    }
+}
@@ -9,6 +9,12 @@ pub struct TemplateRenderer {
     registry: Handlebars<'static>,
 }
 
+impl Default for TemplateRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TemplateRenderer {
     pub fn new() -> Self {
         let mut registry = Handlebars::new();